@@ -1,20 +1,25 @@
 //! A JSON parser that yields somewhat reasonable errors. Its entry point is [`element`].
 //! JSON's grammar is defined at <https://json.org>.
-//! Note that this parser does not consider hex numbers, exponents, or signs.
+//! Note that this parser is more lenient with numbers than JSON, accepting
+//! e.g. `+1` and `.5`.
 
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt};
 
 use hair::{
-    primitive::{any, unit},
+    error::Merge,
+    primitive::{any_merge, recursive},
+    report::Report,
+    text::{self, Escapes, TextError},
     util::recognize_input,
-    ParseResult, Parser,
+    Label, Parser,
 };
 
 fn main() {
     let json = include_str!("data.json");
-    let result = element().parse(json);
-
-    println!("{result:#?}");
+    match element().parse_at(json, 0) {
+        Ok((value, _)) => println!("{value:#?}"),
+        Err(err) => print!("{}", Report::expected(json, &err).with_file("data.json")),
+    }
 }
 
 /// A JSON value.
@@ -37,6 +42,48 @@ pub enum Expect {
     Char(char),
     String(&'static str),
     Rule(&'static str),
+    OneOf(Vec<Expect>),
+}
+
+impl Label for Expect {
+    fn label(label: &'static str) -> Self {
+        Expect::Rule(label)
+    }
+}
+
+impl Merge for Expect {
+    fn merge(self, other: Self) -> Self {
+        let mut expected = match self {
+            Expect::OneOf(expected) => expected,
+            expect => vec![expect],
+        };
+        match other {
+            Expect::OneOf(mut other) => expected.append(&mut other),
+            other => expected.push(other),
+        }
+
+        Expect::OneOf(expected)
+    }
+}
+
+impl fmt::Display for Expect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expect::Char(c) => write!(f, "{c:?}"),
+            Expect::String(s) => write!(f, "{s:?}"),
+            Expect::Rule(rule) => write!(f, "{rule}"),
+            Expect::OneOf(expected) => {
+                write!(f, "one of ")?;
+                for (i, expect) in expected.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{expect}")?;
+                }
+                Ok(())
+            }
+        }
+    }
 }
 
 fn just<'a>(string: &'static str) -> Parser<'a, &'a str, &'a str, Expect> {
@@ -47,66 +94,62 @@ pub fn ws<'a>() -> Parser<'a, &'a str, &'a str, Expect> {
     recognize_input(|c: &char| c.is_whitespace())
         .optional()
         .map(|s| s.unwrap_or(""))
-        .map_err(|_| unreachable!())
+        // `optional` never fails, but the missing whitespace it backtracks
+        // over is still recorded
+        .map_err(|_| Expect::Rule("whitespace"))
 }
 
 pub fn string<'a>() -> Parser<'a, &'a str, String, Expect> {
-    let u = just("\\\\")
-        .or(just("\\\""))
-        .ignore()
-        .ignore_err()
-        .or(unit::<str>().filter(|&c| c != '"').ignore());
-
-    u.many()
-        .input()
-        .map_err(|_| Expect::Rule("string"))
-        .surround(just("\""), just("\"").expect())
-        .map(String::from)
+    text::string('"', Escapes::json())
+        .map_err(|err| match err {
+            TextError::InvalidEscape => Expect::Rule("escape sequence"),
+            TextError::Unterminated => Expect::Char('"'),
+            TextError::Unexpected | TextError::Overflow => Expect::Rule("string"),
+        })
+        .labelled("string")
 }
 
 pub fn number<'a>() -> Parser<'a, &'a str, f32, Expect> {
-    let digits = || recognize_input(char::is_ascii_digit).map_err(|_| Expect::Rule("digit"));
-
-    digits()
-        .then(just(".").then(digits()).optional())
-        .input()
-        .map(|n| n.parse().unwrap())
+    text::float()
+        .map_err(|_| Expect::Rule("number"))
+        .labelled("number")
 }
 
-pub fn value<'a>() -> Parser<'a, &'a str, Value, Expect> {
-    // recursive parsers can be defined with an inner function
-    fn inner(input: &str, at: usize) -> ParseResult<Value, Expect> {
-        let object = {
-            let member = string()
-                .surround(ws(), ws())
-                .then(just(":").expect().right(element().expect()));
-
-            member
-                .separate(just(","))
-                .surround(just("{"), just("}").expect())
-                .map(|members| Value::Object(members.into_iter().collect()))
-        };
+pub fn value<'a>(
+    element: Parser<'a, &'a str, Value, Expect>,
+) -> Parser<'a, &'a str, Value, Expect> {
+    let object = {
+        let member = string()
+            .surround(ws(), ws())
+            .then(just(":").expect().right(element.clone().expect()))
+            .context("object member");
 
-        let array = element()
+        member
             .separate(just(","))
-            .surround(just("["), just("]").expect())
-            .map(Value::Array);
-
-        any([
-            object,
-            array,
-            just("true").map(|_| Value::True),
-            just("false").map(|_| Value::False),
-            just("null").map(|_| Value::Null),
-            string().map(Value::String),
-            number().map(Value::Number),
-        ])
-        .parse_at(input, at)
-    }
-
-    Parser::new(inner)
+            .surround(just("{"), just("}").expect())
+            .map(|members| Value::Object(members.into_iter().collect()))
+            .context("object")
+    };
+
+    let array = element
+        .separate(just(","))
+        .surround(just("["), just("]").expect())
+        .map(Value::Array)
+        .context("array");
+
+    any_merge([
+        object,
+        array,
+        just("true").map(|_| Value::True),
+        just("false").map(|_| Value::False),
+        just("null").map(|_| Value::Null),
+        string().map(Value::String),
+        number().map(Value::Number),
+    ])
+    .labelled("value")
 }
 
 pub fn element<'a>() -> Parser<'a, &'a str, Value, Expect> {
-    value().surround(ws(), ws())
+    // the grammar is built once, with `element` standing for itself
+    recursive(|element| value(element).surround(ws(), ws()))
 }
@@ -0,0 +1,56 @@
+#!/usr/bin/env python3
+"""Generate src/text/xid.rs from the Unicode Character Database.
+
+Usage:
+
+    curl -O https://www.unicode.org/Public/17.0.0/ucd/DerivedCoreProperties.txt
+    python3 scripts/xid.py 17.0.0 DerivedCoreProperties.txt > src/text/xid.rs
+
+The tables are the ranges of characters with the properties XID_Start and
+XID_Continue, merged where adjacent, and sorted for binary search.
+"""
+
+import sys
+
+
+def ranges(path, prop):
+    chars = []
+    with open(path, encoding="utf-8") as ucd:
+        for line in ucd:
+            fields = line.split("#")[0].split(";")
+            if len(fields) != 2 or fields[1].strip() != prop:
+                continue
+            start, _, end = fields[0].strip().partition("..")
+            chars.append((int(start, 16), int(end or start, 16)))
+
+    merged = []
+    for start, end in sorted(chars):
+        if merged and merged[-1][1] + 1 == start:
+            merged[-1] = (merged[-1][0], end)
+        else:
+            merged.append((start, end))
+    return merged
+
+
+def table(name, path, prop):
+    print(f"pub(super) const {name}: &[(char, char)] = &[")
+    for start, end in ranges(path, prop):
+        print(f"    ('\\u{{{start:x}}}', '\\u{{{end:x}}}'),")
+    print("];")
+
+
+def main():
+    version, path = sys.argv[1:]
+    print(f"""\
+//! The ranges of characters with the Unicode properties `XID_Start` and
+//! `XID_Continue`, sorted for binary search.
+//!
+//! Generated from `DerivedCoreProperties.txt` of Unicode {version} by
+//! `scripts/xid.py`; see there to regenerate it. Do not edit by hand.
+""")
+    table("START", path, "XID_Start")
+    table("CONTINUE", path, "XID_Continue")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,236 @@
+//! Parsers for binary data, over `&[u8]`: fixed-width numbers, byte strings,
+//! length-prefixed frames and fields narrower than a byte.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use hair::binary::{self, Endian};
+//!
+//! // a magic number, a big-endian version, then a name prefixed with its length
+//! let header = binary::magic(b"HAIR")
+//!     .right(binary::u16(Endian::Big))
+//!     .then(binary::length_prefixed(binary::u8(), binary::rest()).map_err(|_| ()));
+//!
+//! let data = b"HAIR\x00\x02\x05hello";
+//! assert_eq!(header.parse(data), Ok((2, b"hello".as_slice())));
+//! ```
+
+use crate::{primitive, Error, Parser, Recover, Session};
+
+/// The order of the bytes of a number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endian {
+    Big,
+    Little,
+    /// The byte order of the target platform.
+    Native,
+}
+
+/// Parse exactly `n` bytes.
+pub fn take<'a>(n: usize) -> Parser<'a, &'a [u8], &'a [u8], ()> {
+    Parser::new(move |input: &'a [u8], at, session| {
+        // no input is long enough for more bytes than there are offsets
+        let Some(end) = at.checked_add(n) else {
+            return Err(Error::new((), at));
+        };
+        session.read_to(end);
+        match input.get(at..end) {
+            Some(bytes) => Ok((bytes, end)),
+            None => Err(session.out_of_input((), at, end - input.len())),
+        }
+    })
+}
+
+/// Parse the rest of the input, which may be empty.
+pub fn rest<'a>() -> Parser<'a, &'a [u8], &'a [u8], ()> {
+    Parser::new(|input: &'a [u8], at, session| {
+        session.read_to(input.len() + 1);
+        Ok((&input[at..], input.len()))
+    })
+}
+
+/// Parse exactly `N` bytes into an array.
+pub fn array<'a, const N: usize>() -> Parser<'a, &'a [u8], [u8; N], ()> {
+    take(N).map(|bytes| bytes.try_into().unwrap())
+}
+
+/// If the remaining input starts with `magic`, output the match. This is
+/// [`just`](primitive::just) for byte strings, for identifying file formats
+/// and the like.
+pub fn magic<'a, 'b: 'a>(magic: &'b [u8]) -> Parser<'a, &'a [u8], &'a [u8], ()> {
+    primitive::just(magic)
+}
+
+/// Parse a `u8`.
+pub fn u8<'a>() -> Parser<'a, &'a [u8], u8, ()> {
+    array().map(u8::from_be_bytes)
+}
+
+/// Parse an `i8`.
+pub fn i8<'a>() -> Parser<'a, &'a [u8], i8, ()> {
+    array().map(i8::from_be_bytes)
+}
+
+macro_rules! numbers {
+    ($($ty:ident),*) => {$(
+        #[doc = concat!("Parse a `", stringify!($ty), "` in the given byte order.")]
+        pub fn $ty<'a>(endian: Endian) -> Parser<'a, &'a [u8], $ty, ()> {
+            array().map(move |bytes| match endian {
+                Endian::Big => $ty::from_be_bytes(bytes),
+                Endian::Little => $ty::from_le_bytes(bytes),
+                Endian::Native => $ty::from_ne_bytes(bytes),
+            })
+        }
+    )*};
+}
+
+numbers!(u16, u32, u64, u128, i16, i32, i64, i128, f32, f64);
+
+/// Parse a length with `len`, then parse that many bytes following it
+/// with `inner`. Parsing continues after those bytes, whether or not `inner`
+/// consumed all of them; `inner` can be followed by
+/// [`end`](primitive::end) to require that.
+///
+/// Fails with `None` if the length doesn't fit in the input.
+pub fn length_prefixed<'a, L, O, E, St>(
+    len: Parser<'a, &'a [u8], L, E, St>,
+    inner: Parser<'a, &'a [u8], O, E, St>,
+) -> Parser<'a, &'a [u8], O, Option<E>, St>
+where
+    L: TryInto<usize> + 'a,
+    O: 'a,
+    E: 'a,
+    St: 'a,
+{
+    Parser::new(move |input: &'a [u8], at, session| {
+        let (n, start) = session
+            .nest(Some, |session| len.parse_with(input, at, session))
+            .map_err(|err| err.map(Some))?;
+        let end = n
+            .try_into()
+            .ok()
+            .and_then(|n| start.checked_add(n))
+            .ok_or(Error::new(None, at))?;
+        session.read_to(end);
+        if end > input.len() {
+            return Err(session.out_of_input(None, at, end - input.len()));
+        }
+
+        let (o, _) = session
+            .nest(Some, |session| {
+                // the end of the frame is the end of `inner`'s input, even in
+                // a stream
+                session.truncated(end, |session| {
+                    inner.parse_with(&input[..end], start, session)
+                })
+            })
+            .map_err(|err| err.map(Some))?;
+        Ok((o, end))
+    })
+}
+
+/// Input for parsers of bit fields, see [`bits`]. Offsets into it count
+/// bits, most significant first.
+#[derive(Clone, Copy, Debug)]
+pub struct Bits<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Bits<'a> {
+    /// The number of bits.
+    pub fn len(&self) -> usize {
+        self.bytes.len() * 8
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// The bit at offset `at`.
+    pub fn get(&self, at: usize) -> Option<bool> {
+        let byte = self.bytes.get(at / 8)?;
+        Some(byte & (0x80 >> (at % 8)) != 0)
+    }
+}
+
+/// Parse bit fields with `inner`, starting at the current byte. Parsing
+/// continues at the byte after the last bit `inner` consumed, skipping any
+/// bits left in it. Errors, including those recorded in the session, are
+/// reported at the byte that the failing bit is in, as are their
+/// [spans](Error::span) and [context](Error::context), and bits read mark
+/// their whole byte as read. Outputs aren't converted, so spans that `inner`
+/// outputs, e.g. with [`Parser::with_span`], count bits.
+///
+/// # Example
+///
+/// ```rust
+/// use hair::binary::{bit, bits, take_bits, u8};
+///
+/// // a flag, followed by a 3 bit number, padded to a whole byte
+/// let header = bits(bit().then(take_bits(3))).then(u8());
+/// assert_eq!(header.parse(&[0b1011_0000, 7]), Ok(((true, 0b011), 7)));
+/// ```
+pub fn bits<'a, O, E, St>(inner: Parser<'a, Bits<'a>, O, E, St>) -> Parser<'a, &'a [u8], O, E, St>
+where
+    O: 'a,
+    E: 'a,
+    St: 'a,
+{
+    let to_bytes = |err: Error<E>| {
+        let err = err.map_offsets(|bit| bit / 8);
+        Error {
+            recover: match err.recover {
+                Recover::Incomplete { needed } => Recover::Incomplete {
+                    needed: needed.div_ceil(8),
+                },
+                recover => recover,
+            },
+            ..err
+        }
+    };
+
+    Parser::new(
+        move |input: &'a [u8], at, session: &mut Session<'a, E, St>| {
+            let bits = Bits { bytes: input };
+            let ((result, records), read) = session.reading(at * 8, |session| {
+                session.capture(|session| inner.parse_with(bits, at * 8, session))
+            });
+            session.read_to(read.div_ceil(8));
+            session.replay(records.map(to_bytes));
+
+            result
+                .map(|(o, rest)| (o, rest.div_ceil(8)))
+                .map_err(to_bytes)
+        },
+    )
+}
+
+/// Parse a single bit.
+pub fn bit<'a>() -> Parser<'a, Bits<'a>, bool, ()> {
+    Parser::new(|input: Bits<'a>, at, session| {
+        session.read_to(at + 1);
+        match input.get(at) {
+            Some(bit) => Ok((bit, at + 1)),
+            None => Err(session.out_of_input((), at, 1)),
+        }
+    })
+}
+
+/// Parse `n` bits into an unsigned number, most significant bit first.
+///
+/// # Panics
+///
+/// Panics if `n` is greater than 64.
+pub fn take_bits<'a>(n: usize) -> Parser<'a, Bits<'a>, u64, ()> {
+    assert!(n <= 64, "can't take more than 64 bits at once");
+
+    Parser::new(move |input: Bits<'a>, at, session| {
+        session.read_to(at + n);
+        if input.len() < at + n {
+            return Err(session.out_of_input((), at, at + n - input.len()));
+        }
+
+        let value = (at..at + n).fold(0, |value, i| value << 1 | u64::from(input.get(i).unwrap()));
+        Ok((value, at + n))
+    })
+}
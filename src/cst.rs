@@ -0,0 +1,469 @@
+//! Lossless concrete syntax trees, which keep every byte of the input,
+//! trivia like whitespace and comments included.
+//!
+//! Parsers build a *green* tree with [`node`] and [`token`]: tokens own their
+//! text, and nodes their children, but neither knows where it is, so that
+//! subtrees can be shared. A [`SyntaxNode`] over the root is the *red* tree,
+//! which computes offsets and parents as it is walked.
+//!
+//! A node's children are whatever its parser outputs, flattened by
+//! [`IntoChildren`]. All of the input consumed inside a node must end up in
+//! its tokens for the tree to be lossless. This is up to the grammar, e.g.
+//! skipped whitespace must still be a token, and is checked in debug builds.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use hair::{cst::{node, token, SyntaxNode}, primitive::just, util::recognize_input};
+//!
+//! #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+//! enum Kind { Sum, Number, Plus, Whitespace }
+//!
+//! let ws = token(Kind::Whitespace, recognize_input(|c: &char| c.is_whitespace())).optional();
+//! let number = token(Kind::Number, recognize_input(|c: &char| c.is_ascii_digit()));
+//! let sum = node(
+//!     Kind::Sum,
+//!     number.clone().then(ws.clone().then(token(Kind::Plus, just("+"))).then(ws).then(number).many()),
+//! );
+//!
+//! let source = "1 + 2+ 3";
+//! let tree = SyntaxNode::new_root(sum.parse(source).unwrap());
+//! assert_eq!(tree.text(), source);
+//!
+//! let token = tree.token_at(5).unwrap();
+//! assert_eq!((token.kind(), token.text(), token.span()), (Kind::Plus, "+", 5..6));
+//! assert_eq!(token.parent().kind(), Kind::Sum);
+//! ```
+
+use std::{fmt, ops::Range};
+
+use crate::{sync::Rc, MaybeSync, Parser};
+
+/// A node of the green tree: a kind, and children that cover its text.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct GreenNode<K> {
+    kind: K,
+    len: usize,
+    children: Vec<GreenElement<K>>,
+    /// The offset of each child from the start of the node.
+    offsets: Vec<usize>,
+}
+
+/// A token of the green tree: a kind, and a piece of the input.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct GreenToken<K> {
+    kind: K,
+    text: Box<str>,
+}
+
+/// A child of a [`GreenNode`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum GreenElement<K> {
+    Node(Rc<GreenNode<K>>),
+    Token(Rc<GreenToken<K>>),
+}
+
+impl<K> GreenNode<K> {
+    pub fn new(kind: K, children: Vec<GreenElement<K>>) -> Self {
+        let mut len = 0;
+        let offsets = children
+            .iter()
+            .map(|child| {
+                let offset = len;
+                len += child.len();
+                offset
+            })
+            .collect();
+        Self {
+            kind,
+            len,
+            children,
+            offsets,
+        }
+    }
+
+    pub fn kind(&self) -> &K {
+        &self.kind
+    }
+
+    /// The length of the node's text, in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn children(&self) -> &[GreenElement<K>] {
+        &self.children
+    }
+
+    /// The text of all the node's tokens, in order.
+    pub fn text(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl<K> fmt::Display for GreenNode<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.children
+            .iter()
+            .try_for_each(|child| write!(f, "{child}"))
+    }
+}
+
+impl<K> GreenToken<K> {
+    pub fn new(kind: K, text: &str) -> Self {
+        Self {
+            kind,
+            text: text.into(),
+        }
+    }
+
+    pub fn kind(&self) -> &K {
+        &self.kind
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The length of the token's text, in bytes.
+    pub fn len(&self) -> usize {
+        self.text.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+}
+
+impl<K> GreenElement<K> {
+    pub fn kind(&self) -> &K {
+        match self {
+            GreenElement::Node(node) => node.kind(),
+            GreenElement::Token(token) => token.kind(),
+        }
+    }
+
+    /// The length of the element's text, in bytes.
+    pub fn len(&self) -> usize {
+        match self {
+            GreenElement::Node(node) => node.len(),
+            GreenElement::Token(token) => token.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K> fmt::Display for GreenElement<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GreenElement::Node(node) => write!(f, "{node}"),
+            GreenElement::Token(token) => f.write_str(&token.text),
+        }
+    }
+}
+
+/// Outputs of parsers that can be the children of a [`node`]. Tuples, as
+/// output by [`Parser::then`], and collections are flattened in order, and
+/// `()` has no children.
+pub trait IntoChildren<K> {
+    fn into_children(self, children: &mut Vec<GreenElement<K>>);
+}
+
+impl<K> IntoChildren<K> for GreenElement<K> {
+    fn into_children(self, children: &mut Vec<GreenElement<K>>) {
+        children.push(self);
+    }
+}
+
+impl<K> IntoChildren<K> for Rc<GreenNode<K>> {
+    fn into_children(self, children: &mut Vec<GreenElement<K>>) {
+        children.push(GreenElement::Node(self));
+    }
+}
+
+impl<K> IntoChildren<K> for Rc<GreenToken<K>> {
+    fn into_children(self, children: &mut Vec<GreenElement<K>>) {
+        children.push(GreenElement::Token(self));
+    }
+}
+
+impl<K> IntoChildren<K> for () {
+    fn into_children(self, _: &mut Vec<GreenElement<K>>) {}
+}
+
+impl<K, T: IntoChildren<K>> IntoChildren<K> for Option<T> {
+    fn into_children(self, children: &mut Vec<GreenElement<K>>) {
+        if let Some(t) = self {
+            t.into_children(children);
+        }
+    }
+}
+
+impl<K, T: IntoChildren<K>> IntoChildren<K> for Vec<T> {
+    fn into_children(self, children: &mut Vec<GreenElement<K>>) {
+        for t in self {
+            t.into_children(children);
+        }
+    }
+}
+
+impl<K, A: IntoChildren<K>, B: IntoChildren<K>> IntoChildren<K> for (A, B) {
+    fn into_children(self, children: &mut Vec<GreenElement<K>>) {
+        self.0.into_children(children);
+        self.1.into_children(children);
+    }
+}
+
+/// Parse with `parser`, and output a node of `kind` with the children it
+/// outputs, which must cover all of the input that `parser` consumed.
+///
+/// # Panics
+///
+/// In debug builds, panics if the children don't cover all of the input that
+/// `parser` consumed.
+pub fn node<'a, K, C, E, St>(
+    kind: K,
+    parser: Parser<'a, &'a str, C, E, St>,
+) -> Parser<'a, &'a str, Rc<GreenNode<K>>, E, St>
+where
+    K: Clone + MaybeSync + 'a,
+    C: IntoChildren<K> + 'a,
+    E: 'a,
+    St: 'a,
+{
+    // the node's output holds no offsets, so unlike `with_span`, this
+    // doesn't keep it from being reused after an edit moves it
+    let grammar = parser.grammar.clone();
+    Parser::new(move |input, at, session| {
+        let (c, rest) = parser.parse_with(input, at, session)?;
+        let mut children = Vec::new();
+        c.into_children(&mut children);
+        let node = GreenNode::new(kind.clone(), children);
+        debug_assert_eq!(
+            node.len(),
+            rest - at,
+            "the input consumed in a node must be covered by its tokens"
+        );
+        Ok((Rc::new(node), rest))
+    })
+    .with_grammar(grammar)
+}
+
+/// Parse with `parser`, and output a token of `kind` with the text it
+/// consumed.
+pub fn token<'a, K, O, E, St>(
+    kind: K,
+    parser: Parser<'a, &'a str, O, E, St>,
+) -> Parser<'a, &'a str, Rc<GreenToken<K>>, E, St>
+where
+    K: Clone + MaybeSync + 'a,
+    O: 'a,
+    E: 'a,
+    St: 'a,
+{
+    parser
+        .input()
+        .map(move |text| Rc::new(GreenToken::new(kind.clone(), text)))
+}
+
+/// A node of the red tree: a [`GreenNode`] at an offset, with a parent.
+/// Cloning is cheap.
+#[derive(Clone)]
+pub struct SyntaxNode<K> {
+    data: Rc<NodeData<K>>,
+}
+
+struct NodeData<K> {
+    green: Rc<GreenNode<K>>,
+    offset: usize,
+    parent: Option<SyntaxNode<K>>,
+}
+
+/// A token of the red tree: a [`GreenToken`] at an offset, in a node.
+#[derive(Clone)]
+pub struct SyntaxToken<K> {
+    green: Rc<GreenToken<K>>,
+    offset: usize,
+    parent: SyntaxNode<K>,
+}
+
+/// A child of a [`SyntaxNode`].
+#[derive(Clone)]
+pub enum SyntaxElement<K> {
+    Node(SyntaxNode<K>),
+    Token(SyntaxToken<K>),
+}
+
+impl<K: Clone> SyntaxNode<K> {
+    /// The root of the tree of `green`, at offset 0.
+    pub fn new_root(green: Rc<GreenNode<K>>) -> Self {
+        Self {
+            data: Rc::new(NodeData {
+                green,
+                offset: 0,
+                parent: None,
+            }),
+        }
+    }
+
+    pub fn kind(&self) -> K {
+        self.data.green.kind.clone()
+    }
+
+    pub fn green(&self) -> &Rc<GreenNode<K>> {
+        &self.data.green
+    }
+
+    pub fn span(&self) -> Range<usize> {
+        self.data.offset..self.data.offset + self.data.green.len()
+    }
+
+    pub fn text(&self) -> String {
+        self.data.green.text()
+    }
+
+    pub fn parent(&self) -> Option<&SyntaxNode<K>> {
+        self.data.parent.as_ref()
+    }
+
+    pub fn children(&self) -> Vec<SyntaxElement<K>> {
+        (0..self.data.green.children.len())
+            .map(|i| self.child(i))
+            .collect()
+    }
+
+    /// The red element of the child at index `i`.
+    fn child(&self, i: usize) -> SyntaxElement<K> {
+        let offset = self.data.offset + self.data.green.offsets[i];
+        match &self.data.green.children[i] {
+            GreenElement::Node(green) => SyntaxElement::Node(SyntaxNode {
+                data: Rc::new(NodeData {
+                    green: Rc::clone(green),
+                    offset,
+                    parent: Some(self.clone()),
+                }),
+            }),
+            GreenElement::Token(green) => SyntaxElement::Token(SyntaxToken {
+                green: Rc::clone(green),
+                offset,
+                parent: self.clone(),
+            }),
+        }
+    }
+
+    /// The child that contains `offset`. At the boundary of two children, the
+    /// one starting there is chosen.
+    fn child_at(&self, offset: usize) -> Option<SyntaxElement<K>> {
+        let green = &self.data.green;
+        let offset = offset.checked_sub(self.data.offset)?;
+        // the first child starting at the offset, even if empty, or else the
+        // one before it, if it reaches past the offset
+        let i = green.offsets.partition_point(|&start| start < offset);
+        if green.offsets.get(i) == Some(&offset) {
+            return Some(self.child(i));
+        }
+        let i = i.checked_sub(1)?;
+        (green.offsets[i] + green.children[i].len() > offset).then(|| self.child(i))
+    }
+
+    /// The token that contains `offset`, or `None` if the offset is outside
+    /// of the node. At the boundary of two tokens, the one starting there is
+    /// chosen.
+    pub fn token_at(&self, offset: usize) -> Option<SyntaxToken<K>> {
+        match self.child_at(offset)? {
+            SyntaxElement::Node(node) => node.token_at(offset),
+            SyntaxElement::Token(token) => Some(token),
+        }
+    }
+
+    /// The innermost node that contains `offset`, or `None` if the offset is
+    /// outside of the node.
+    pub fn node_at(&self, offset: usize) -> Option<SyntaxNode<K>> {
+        if !self.span().contains(&offset) {
+            return None;
+        }
+        match self.child_at(offset) {
+            Some(SyntaxElement::Node(node)) => node.node_at(offset).or(Some(node)),
+            _ => Some(self.clone()),
+        }
+    }
+}
+
+impl<K> fmt::Display for SyntaxNode<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.data.green)
+    }
+}
+
+impl<K: fmt::Debug> fmt::Debug for SyntaxNode<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let offset = self.data.offset;
+        write!(
+            f,
+            "{:?}@{:?}",
+            self.data.green.kind,
+            offset..offset + self.data.green.len()
+        )
+    }
+}
+
+impl<K: Clone> SyntaxToken<K> {
+    pub fn kind(&self) -> K {
+        self.green.kind.clone()
+    }
+
+    pub fn green(&self) -> &Rc<GreenToken<K>> {
+        &self.green
+    }
+
+    pub fn span(&self) -> Range<usize> {
+        self.offset..self.offset + self.green.len()
+    }
+
+    pub fn text(&self) -> &str {
+        &self.green.text
+    }
+
+    pub fn parent(&self) -> &SyntaxNode<K> {
+        &self.parent
+    }
+}
+
+impl<K: fmt::Debug> fmt::Debug for SyntaxToken<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let span = self.offset..self.offset + self.green.len();
+        write!(f, "{:?}@{:?} {:?}", self.green.kind, span, self.green.text)
+    }
+}
+
+impl<K: Clone> SyntaxElement<K> {
+    pub fn kind(&self) -> K {
+        match self {
+            SyntaxElement::Node(node) => node.kind(),
+            SyntaxElement::Token(token) => token.kind(),
+        }
+    }
+
+    pub fn span(&self) -> Range<usize> {
+        match self {
+            SyntaxElement::Node(node) => node.span(),
+            SyntaxElement::Token(token) => token.span(),
+        }
+    }
+}
+
+impl<K: fmt::Debug> fmt::Debug for SyntaxElement<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyntaxElement::Node(node) => node.fmt(f),
+            SyntaxElement::Token(token) => token.fmt(f),
+        }
+    }
+}
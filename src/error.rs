@@ -0,0 +1,278 @@
+//! See [`Error`] and [`Merge`].
+
+use std::{collections::BTreeSet, ops::Range};
+
+/// This type wraps errors as they propagate upward through parsers. `E` is the
+/// parser's actual error type, whether it be `()` or a user-defined error.
+///
+/// hair borrows the error propagation mechanism seen in some other combinator
+/// libraries, such as nom. Errors have a state of 'recoverable' or 'fatal',
+/// where fatal errors will always propagate upward regardless of alternatives.
+/// hair's primitive combinators will never yield a fatal error - it is
+/// up to the user to decide which parsers should throw fatal errors with
+/// [`Parser::expect`](crate::Parser::expect).
+///
+/// Introducing fatal-throwing parsers will never cause another failing parser
+/// to succeed. It is only for providing more accurate error messages.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Error<E> {
+    pub(crate) inner: E,
+    pub(crate) recover: Recover,
+    pub(crate) at: usize,
+    /// Where the rule that failed started, see [`Error::span`].
+    pub(crate) start: usize,
+    pub(crate) label: Option<&'static str>,
+    pub(crate) context: Vec<Context>,
+}
+
+impl<E> Error<E> {
+    pub fn new(inner: E, at: usize) -> Self {
+        Self {
+            inner,
+            recover: Recover::Recoverable,
+            at,
+            start: at,
+            label: None,
+            context: Vec::new(),
+        }
+    }
+
+    /// The user-defined error value.
+    pub fn inner(&self) -> &E {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> E {
+        self.inner
+    }
+
+    /// The offset into the input at which the error occurred.
+    pub fn at(&self) -> usize {
+        self.at
+    }
+
+    pub fn recover(&self) -> Recover {
+        self.recover
+    }
+
+    /// The range of the input from the start of the rule that failed to
+    /// where it gave up, at [`Error::at`]. Rules are marked with
+    /// [`Parser::labelled`](crate::Parser::labelled); errors outside of any
+    /// rule have an empty span.
+    pub fn span(&self) -> Range<usize> {
+        self.start..self.at
+    }
+
+    /// The name of the rule that failed, see
+    /// [`Parser::labelled`](crate::Parser::labelled).
+    pub fn label(&self) -> Option<&'static str> {
+        self.label
+    }
+
+    /// The constructs that were being parsed when the error occurred,
+    /// innermost first. See [`Parser::context`](crate::Parser::context).
+    pub fn context(&self) -> &[Context] {
+        &self.context
+    }
+
+    /// Map the error's inner value.
+    pub fn map<F, E1>(self, f: F) -> Error<E1>
+    where
+        F: Fn(E) -> E1,
+    {
+        let Error {
+            inner,
+            recover,
+            at,
+            start,
+            label,
+            context,
+        } = self;
+        Error {
+            inner: f(inner),
+            recover,
+            at,
+            start,
+            label,
+            context,
+        }
+    }
+
+    /// Move the error and its spans by `delta`.
+    pub(crate) fn shift(self, delta: isize) -> Self {
+        self.map_offsets(|offset| offset.wrapping_add_signed(delta))
+    }
+
+    /// Convert every offset of the error and its spans with `f`, e.g. into
+    /// offsets of another input.
+    pub(crate) fn map_offsets(self, f: impl Fn(usize) -> usize) -> Self {
+        Error {
+            at: f(self.at),
+            start: f(self.start),
+            context: self
+                .context
+                .into_iter()
+                .map(|context| Context {
+                    span: f(context.span.start)..f(context.span.end),
+                    ..context
+                })
+                .collect(),
+            ..self
+        }
+    }
+
+    /// Make this error fatal, unless it's [`Recover::Incomplete`].
+    #[must_use]
+    pub fn fail(self) -> Error<E> {
+        match self.recover {
+            Recover::Incomplete { .. } => self,
+            _ => Error {
+                recover: Recover::Fatal,
+                ..self
+            },
+        }
+    }
+}
+
+impl<E: Merge> Error<E> {
+    /// Combine two errors from alternative parsers. The error that got further
+    /// into the input is kept; errors at the same offset have their inner
+    /// values merged with [`Merge::merge`], and cover the spans of both. The
+    /// result is fatal if either error was.
+    #[must_use]
+    pub fn merge(self, other: Error<E>) -> Error<E> {
+        match self.at.cmp(&other.at) {
+            std::cmp::Ordering::Greater => self,
+            std::cmp::Ordering::Less => other,
+            std::cmp::Ordering::Equal => Error {
+                inner: self.inner.merge(other.inner),
+                recover: self.recover.max(other.recover),
+                at: self.at,
+                start: self.start.min(other.start),
+                // the errors are of different rules unless they agree
+                label: self.label.filter(|_| self.label == other.label),
+                context: if self.context.is_empty() {
+                    other.context
+                } else {
+                    self.context
+                },
+            },
+        }
+    }
+}
+
+/// A frame of [`Error::context`]: a construct that was being parsed, and
+/// the span from its start to the error.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Context {
+    pub label: &'static str,
+    pub span: Range<usize>,
+}
+
+/// State within [`Error`]. Errors other than `Recover::Recoverable`
+/// short-circuit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Recover {
+    Recoverable,
+    Fatal,
+    /// The parser ran out of input that is only partially available, and
+    /// needs at least `needed` more units of it to decide. The inner error
+    /// is the one the parser would fail with if the input ended there. See
+    /// [`stream`](crate::stream).
+    Incomplete {
+        needed: usize,
+    },
+}
+
+/// Errors that can stand for what a rule expected, given the rule's name.
+/// When a [labelled](crate::Parser::labelled) parser fails without consuming
+/// any input, its error is replaced with its label.
+///
+/// # Example
+///
+/// ```rust
+/// use hair::primitive::{any_merge, just};
+///
+/// let literal = |s| just(s).map_err(|_| Vec::new()).labelled(s);
+/// let boolean = any_merge([literal("true"), literal("false")]);
+/// assert_eq!(boolean.parse("yes"), Err((vec!["true", "false"], 0)));
+///
+/// let boolean = boolean.labelled("boolean");
+/// assert_eq!(boolean.parse("yes"), Err((vec!["boolean"], 0)));
+/// ```
+pub trait Label {
+    fn label(label: &'static str) -> Self;
+}
+
+impl Label for () {
+    fn label(_: &'static str) -> Self {}
+}
+
+impl<T: From<&'static str>> Label for Vec<T> {
+    fn label(label: &'static str) -> Self {
+        vec![label.into()]
+    }
+}
+
+impl<T: Ord + From<&'static str>> Label for BTreeSet<T> {
+    fn label(label: &'static str) -> Self {
+        BTreeSet::from([label.into()])
+    }
+}
+
+impl<E: Label> Label for Option<E> {
+    fn label(label: &'static str) -> Self {
+        Some(E::label(label))
+    }
+}
+
+/// Errors that can be combined when several alternatives fail at the same
+/// offset, as in [`Parser::or_merge`](crate::Parser::or_merge) and
+/// [`any_merge`](crate::primitive::any_merge). Typically, the merged error
+/// holds the set of everything that was expected at that point.
+///
+/// # Example
+///
+/// ```rust
+/// use hair::{error::Merge, primitive::{any_merge, just}};
+///
+/// let keyword = |k: &'static str| just(k).map_err(move |_| vec![k]);
+/// let p = any_merge([keyword("let"), keyword("fn"), keyword("struct")]);
+///
+/// assert_eq!(p.parse("enum"), Err((vec!["let", "fn", "struct"], 0)));
+/// ```
+pub trait Merge {
+    #[must_use]
+    fn merge(self, other: Self) -> Self;
+}
+
+impl Merge for () {
+    fn merge(self, _: Self) -> Self {}
+}
+
+/// Concatenates both vectors.
+impl<T> Merge for Vec<T> {
+    fn merge(mut self, mut other: Self) -> Self {
+        self.append(&mut other);
+        self
+    }
+}
+
+/// Takes the union of both sets.
+impl<T: Ord> Merge for BTreeSet<T> {
+    fn merge(mut self, mut other: Self) -> Self {
+        self.append(&mut other);
+        self
+    }
+}
+
+/// `None` carries no information, so merging with it yields the other value.
+impl<E: Merge> Merge for Option<E> {
+    fn merge(self, other: Self) -> Self {
+        match (self, other) {
+            (Some(a), Some(b)) => Some(a.merge(b)),
+            (a, None) => a,
+            (None, b) => b,
+        }
+    }
+}
@@ -0,0 +1,619 @@
+//! Statically dispatched parsers. See [`ParserExt`].
+//!
+//! Each combinator of [`Parser`] allocates, and calls the parsers it combines
+//! through a trait object, which the compiler can't see through. Parsers
+//! built with [`ParserExt`] are plain structs, generic over the parsers they
+//! combine, so a whole grammar built from them is monomorphised and can be
+//! inlined. The price is in their types, which grow with the grammar.
+//!
+//! The two kinds of parsers mix freely: a [`Parser`] implements
+//! [`ParserExt`], and any [`ParserExt`] can be turned into a [`Parser`] with
+//! [`ParserExt::boxed`]. So a grammar can keep using [`Parser`], and switch to
+//! [`ParserExt`] in its hot spots, such as the tokens of a lexer.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use hair::{
+//!     ext::{self, ParserExt},
+//!     primitive::just,
+//!     Parser,
+//! };
+//!
+//! // a run of digits, without any allocation per digit
+//! let digit = ext::unit::<str>().filter(char::is_ascii_digit);
+//! let number: Parser<&str, u32, ()> = digit
+//!     .many()
+//!     .map(|digits| digits.into_iter().fold(0, |n, d| n * 10 + d.to_digit(10).unwrap()))
+//!     .boxed();
+//!
+//! let sum = number.clone().left(just("+")).then(number);
+//! assert_eq!(sum.parse("12+34"), Ok((12, 34)));
+//! ```
+
+use std::marker::PhantomData;
+
+use crate::{Error, MaybeSync, ParseResult, Parser, Recover, Session, Slice};
+
+/// A parser whose combinators are generic structs, rather than [`Parser`]s.
+///
+/// The combinators behave like those of [`Parser`] with the same names. Note
+/// that a [`Parser`]'s own methods take precedence over these; use
+/// [`ParserExt::boxed`] at the end of a statically dispatched parser, rather
+/// than starting one from a [`Parser`].
+///
+/// Like a [`Parser`], a `ParserExt` is run with user state of type `St`, see
+/// [`Session::state`].
+pub trait ParserExt<'a, I, St = ()> {
+    type Output;
+    type Error;
+
+    /// Parse starting at an offset, within an existing session, like
+    /// [`Parser::parse_with`].
+    fn parse_with(
+        &self,
+        input: I,
+        at: usize,
+        session: &mut Session<'a, Self::Error, St>,
+    ) -> ParseResult<Self::Output, Self::Error>;
+
+    /// Parse from the beginning with the default state, like
+    /// [`Parser::parse`].
+    fn parse(&self, input: I) -> Result<Self::Output, (Self::Error, usize)>
+    where
+        St: Default,
+    {
+        self.parse_with_state(input, St::default()).0
+    }
+
+    /// Parse from the beginning with `state`, like
+    /// [`Parser::parse_with_state`].
+    #[allow(clippy::type_complexity)]
+    fn parse_with_state(
+        &self,
+        input: I,
+        state: St,
+    ) -> (Result<Self::Output, (Self::Error, usize)>, St) {
+        let mut session = Session::with_state(state);
+        let result = self.parse_with(input, 0, &mut session);
+        let state = session.take_state();
+        (crate::output(session.finish_furthest(result)), state)
+    }
+
+    /// Turn this parser into a [`Parser`], to use it with the rest of the
+    /// crate.
+    fn boxed(self) -> Parser<'a, I, Self::Output, Self::Error, St>
+    where
+        Self: Sized + MaybeSync + 'a,
+        I: Clone + 'a,
+        Self::Output: 'a,
+        Self::Error: 'a,
+        St: 'a,
+    {
+        Parser::new(move |input, at, session| self.parse_with(input, at, session))
+    }
+
+    /// See [`Parser::map`].
+    fn map<O1, F>(self, f: F) -> Map<Self, F>
+    where
+        Self: Sized,
+        F: Fn(Self::Output) -> O1,
+    {
+        Map { parser: self, f }
+    }
+
+    /// See [`Parser::map_err`].
+    fn map_err<E1, F>(self, f: F) -> MapErr<Self, F>
+    where
+        Self: Sized,
+        F: Fn(Self::Error) -> E1,
+    {
+        MapErr { parser: self, f }
+    }
+
+    /// See [`Parser::filter`]. Unlike it, this keeps the error type.
+    fn filter<F>(self, predicate: F) -> Filter<Self, F>
+    where
+        Self: Sized,
+        Self::Error: Default,
+        F: Fn(&Self::Output) -> bool,
+    {
+        Filter {
+            parser: self,
+            predicate,
+        }
+    }
+
+    /// See [`Parser::then`].
+    fn then<P>(self, other: P) -> Then<Self, P>
+    where
+        Self: Sized,
+        P: ParserExt<'a, I, St, Error = Self::Error>,
+    {
+        Then(self, other)
+    }
+
+    /// See [`Parser::left`].
+    fn left<P>(self, other: P) -> Left<Self, P>
+    where
+        Self: Sized,
+        P: ParserExt<'a, I, St, Error = Self::Error>,
+    {
+        Left(self, other)
+    }
+
+    /// See [`Parser::right`].
+    fn right<P>(self, other: P) -> Right<Self, P>
+    where
+        Self: Sized,
+        P: ParserExt<'a, I, St, Error = Self::Error>,
+    {
+        Right(self, other)
+    }
+
+    /// See [`Parser::or`].
+    fn or<P>(self, other: P) -> Or<Self, P>
+    where
+        Self: Sized,
+        P: ParserExt<'a, I, St, Output = Self::Output, Error = Self::Error>,
+    {
+        Or(self, other)
+    }
+
+    /// See [`Parser::optional`].
+    fn optional(self) -> Optional<Self>
+    where
+        Self: Sized,
+    {
+        Optional(self)
+    }
+
+    /// See [`Parser::many`].
+    fn many(self) -> Many<Self>
+    where
+        Self: Sized,
+    {
+        Many(self)
+    }
+
+    /// See [`Parser::ignore`].
+    fn ignore(self) -> Ignore<Self>
+    where
+        Self: Sized,
+    {
+        Ignore(self)
+    }
+}
+
+impl<'a, I: Clone + 'a, O: 'a, E: 'a, St: 'a> ParserExt<'a, I, St> for Parser<'a, I, O, E, St> {
+    type Output = O;
+    type Error = E;
+
+    fn parse_with(
+        &self,
+        input: I,
+        at: usize,
+        session: &mut Session<'a, E, St>,
+    ) -> ParseResult<O, E> {
+        Parser::parse_with(self, input, at, session)
+    }
+}
+
+/// See [`from_fn`].
+pub struct FromFn<F, O, E> {
+    f: F,
+    types: PhantomData<fn() -> (O, E)>,
+}
+
+impl<F: Clone, O, E> Clone for FromFn<F, O, E> {
+    fn clone(&self) -> Self {
+        Self {
+            f: self.f.clone(),
+            types: PhantomData,
+        }
+    }
+}
+
+/// A parser from a function, like [`Parser::new`].
+pub fn from_fn<'a, I, O, E, St, F>(f: F) -> FromFn<F, O, E>
+where
+    F: Fn(I, usize, &mut Session<'a, E, St>) -> ParseResult<O, E>,
+{
+    FromFn {
+        f,
+        types: PhantomData,
+    }
+}
+
+impl<'a, I, O, E, St, F> ParserExt<'a, I, St> for FromFn<F, O, E>
+where
+    F: Fn(I, usize, &mut Session<'a, E, St>) -> ParseResult<O, E>,
+{
+    type Output = O;
+    type Error = E;
+
+    fn parse_with(
+        &self,
+        input: I,
+        at: usize,
+        session: &mut Session<'a, E, St>,
+    ) -> ParseResult<O, E> {
+        (self.f)(input, at, session)
+    }
+}
+
+/// See [`unit()`].
+pub struct Unit<S: ?Sized, St = ()>(PhantomData<fn(&S, &mut St)>);
+
+impl<S: ?Sized, St> Unit<S, St> {
+    /// The parser of [`unit()`], for a parse with user state of type `St`.
+    pub fn new() -> Self {
+        Unit(PhantomData)
+    }
+}
+
+impl<S: ?Sized, St> Default for Unit<S, St> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: ?Sized, St> Clone for Unit<S, St> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<S: ?Sized, St> Copy for Unit<S, St> {}
+
+/// Like [`primitive::unit`](crate::primitive::unit), which is this parser
+/// boxed. See [`Unit::new`] for parses with user state.
+pub fn unit<S: ?Sized>() -> Unit<S> {
+    Unit::new()
+}
+
+impl<'a, S: Slice<'a> + ?Sized, St> ParserExt<'a, &'a S, St> for Unit<S, St> {
+    type Output = S::Item;
+    type Error = ();
+
+    fn parse_with(
+        &self,
+        input: &'a S,
+        at: usize,
+        session: &mut Session<'a, (), St>,
+    ) -> ParseResult<S::Item, ()> {
+        let first = input.index_from(at).first();
+        session.read_to(at + first.as_ref().map_or(1, |(_, len)| *len));
+        match first {
+            Some((c, len)) => Ok((c, at + len)),
+            None => Err(session.out_of_input((), at, 1)),
+        }
+    }
+}
+
+/// See [`just`].
+pub struct Just<'b, S: ?Sized, St = ()>(&'b S, PhantomData<fn(&mut St)>);
+
+impl<'b, S: ?Sized, St> Just<'b, S, St> {
+    /// The parser of [`just`], for a parse with user state of type `St`.
+    pub fn new(expected: &'b S) -> Self {
+        Just(expected, PhantomData)
+    }
+}
+
+impl<S: ?Sized, St> Clone for Just<'_, S, St> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<S: ?Sized, St> Copy for Just<'_, S, St> {}
+
+/// Like [`primitive::just`](crate::primitive::just), which is this parser
+/// boxed. See [`Just::new`] for parses with user state.
+pub fn just<S: ?Sized>(expected: &S) -> Just<'_, S> {
+    Just::new(expected)
+}
+
+impl<'a, 'b: 'a, S, St> ParserExt<'a, &'a S, St> for Just<'b, S, St>
+where
+    S: Slice<'a> + PartialEq<S> + ?Sized,
+{
+    type Output = &'a S;
+    type Error = ();
+
+    fn parse_with(
+        &self,
+        input: &'a S,
+        at: usize,
+        session: &mut Session<'a, (), St>,
+    ) -> ParseResult<&'a S, ()> {
+        let expected = self.0;
+        session.read_to(at + expected.len().max(1));
+        let rest = input.index_from(at);
+        if input.is_boundary(at + expected.len())
+            && input.index_between(at, at + expected.len()) == expected
+        {
+            Ok((expected, at + expected.len()))
+        } else if expected.is_boundary(rest.len()) && expected.index_to(rest.len()) == rest {
+            Err(session.out_of_input((), at, expected.len() - rest.len()))
+        } else {
+            Err(Error::new((), at))
+        }
+    }
+}
+
+/// See [`ParserExt::map`].
+#[derive(Clone, Copy)]
+pub struct Map<P, F> {
+    parser: P,
+    f: F,
+}
+
+impl<'a, I, St, O1, P, F> ParserExt<'a, I, St> for Map<P, F>
+where
+    P: ParserExt<'a, I, St>,
+    F: Fn(P::Output) -> O1,
+{
+    type Output = O1;
+    type Error = P::Error;
+
+    fn parse_with(
+        &self,
+        input: I,
+        at: usize,
+        session: &mut Session<'a, P::Error, St>,
+    ) -> ParseResult<O1, P::Error> {
+        self.parser
+            .parse_with(input, at, session)
+            .map(|(o, rest)| ((self.f)(o), rest))
+    }
+}
+
+/// See [`ParserExt::map_err`].
+#[derive(Clone, Copy)]
+pub struct MapErr<P, F> {
+    parser: P,
+    f: F,
+}
+
+impl<'a, I, St, E1, P, F> ParserExt<'a, I, St> for MapErr<P, F>
+where
+    P: ParserExt<'a, I, St>,
+    F: Fn(P::Error) -> E1,
+{
+    type Output = P::Output;
+    type Error = E1;
+
+    fn parse_with(
+        &self,
+        input: I,
+        at: usize,
+        session: &mut Session<'a, E1, St>,
+    ) -> ParseResult<P::Output, E1> {
+        session
+            .nest(&self.f, |session| {
+                self.parser.parse_with(input, at, session)
+            })
+            .map_err(|err| err.map(&self.f))
+    }
+}
+
+/// See [`ParserExt::filter`].
+#[derive(Clone, Copy)]
+pub struct Filter<P, F> {
+    parser: P,
+    predicate: F,
+}
+
+impl<'a, I, St, P, F> ParserExt<'a, I, St> for Filter<P, F>
+where
+    P: ParserExt<'a, I, St>,
+    P::Error: Default,
+    F: Fn(&P::Output) -> bool,
+{
+    type Output = P::Output;
+    type Error = P::Error;
+
+    fn parse_with(
+        &self,
+        input: I,
+        at: usize,
+        session: &mut Session<'a, P::Error, St>,
+    ) -> ParseResult<P::Output, P::Error> {
+        match self.parser.parse_with(input, at, session) {
+            Ok((o, rest)) if (self.predicate)(&o) => Ok((o, rest)),
+            Ok(_) => Err(session.reject(None, P::Error::default(), at)),
+            Err(err) => Err(session.reject(Some(err), P::Error::default(), at)),
+        }
+    }
+}
+
+/// See [`ParserExt::then`].
+#[derive(Clone, Copy)]
+pub struct Then<A, B>(A, B);
+
+impl<'a, I, St, A, B> ParserExt<'a, I, St> for Then<A, B>
+where
+    I: Clone,
+    A: ParserExt<'a, I, St>,
+    B: ParserExt<'a, I, St, Error = A::Error>,
+{
+    type Output = (A::Output, B::Output);
+    type Error = A::Error;
+
+    fn parse_with(
+        &self,
+        input: I,
+        at: usize,
+        session: &mut Session<'a, A::Error, St>,
+    ) -> ParseResult<Self::Output, A::Error> {
+        let (a, rest) = self.0.parse_with(input.clone(), at, session)?;
+        let (b, rest) = self.1.parse_with(input, rest, session)?;
+        Ok(((a, b), rest))
+    }
+}
+
+/// See [`ParserExt::left`].
+#[derive(Clone, Copy)]
+pub struct Left<A, B>(A, B);
+
+impl<'a, I, St, A, B> ParserExt<'a, I, St> for Left<A, B>
+where
+    I: Clone,
+    A: ParserExt<'a, I, St>,
+    B: ParserExt<'a, I, St, Error = A::Error>,
+{
+    type Output = A::Output;
+    type Error = A::Error;
+
+    fn parse_with(
+        &self,
+        input: I,
+        at: usize,
+        session: &mut Session<'a, A::Error, St>,
+    ) -> ParseResult<A::Output, A::Error> {
+        let (a, rest) = self.0.parse_with(input.clone(), at, session)?;
+        let (_, rest) = self.1.parse_with(input, rest, session)?;
+        Ok((a, rest))
+    }
+}
+
+/// See [`ParserExt::right`].
+#[derive(Clone, Copy)]
+pub struct Right<A, B>(A, B);
+
+impl<'a, I, St, A, B> ParserExt<'a, I, St> for Right<A, B>
+where
+    I: Clone,
+    A: ParserExt<'a, I, St>,
+    B: ParserExt<'a, I, St, Error = A::Error>,
+{
+    type Output = B::Output;
+    type Error = A::Error;
+
+    fn parse_with(
+        &self,
+        input: I,
+        at: usize,
+        session: &mut Session<'a, A::Error, St>,
+    ) -> ParseResult<B::Output, A::Error> {
+        let (_, rest) = self.0.parse_with(input.clone(), at, session)?;
+        self.1.parse_with(input, rest, session)
+    }
+}
+
+/// See [`ParserExt::or`].
+#[derive(Clone, Copy)]
+pub struct Or<A, B>(A, B);
+
+impl<'a, I, St, A, B> ParserExt<'a, I, St> for Or<A, B>
+where
+    I: Clone,
+    A: ParserExt<'a, I, St>,
+    B: ParserExt<'a, I, St, Output = A::Output, Error = A::Error>,
+{
+    type Output = A::Output;
+    type Error = A::Error;
+
+    fn parse_with(
+        &self,
+        input: I,
+        at: usize,
+        session: &mut Session<'a, A::Error, St>,
+    ) -> ParseResult<A::Output, A::Error> {
+        let checkpoint = session.checkpoint();
+        match self.0.parse_with(input.clone(), at, session) {
+            Ok(ok) => Ok(ok),
+            Err(err) => match err.recover {
+                Recover::Recoverable => {
+                    session.backtrack(checkpoint, err);
+                    self.1.parse_with(input, at, session)
+                }
+                Recover::Fatal | Recover::Incomplete { .. } => Err(err),
+            },
+        }
+    }
+}
+
+/// See [`ParserExt::optional`].
+#[derive(Clone, Copy)]
+pub struct Optional<P>(P);
+
+impl<'a, I, St, P: ParserExt<'a, I, St>> ParserExt<'a, I, St> for Optional<P> {
+    type Output = Option<P::Output>;
+    type Error = P::Error;
+
+    fn parse_with(
+        &self,
+        input: I,
+        at: usize,
+        session: &mut Session<'a, P::Error, St>,
+    ) -> ParseResult<Self::Output, P::Error> {
+        let checkpoint = session.checkpoint();
+        match self.0.parse_with(input, at, session) {
+            Ok((o, rest)) => Ok((Some(o), rest)),
+            Err(err) => match err.recover {
+                Recover::Recoverable => {
+                    session.backtrack(checkpoint, err);
+                    Ok((None, at))
+                }
+                Recover::Fatal | Recover::Incomplete { .. } => Err(err),
+            },
+        }
+    }
+}
+
+/// See [`ParserExt::many`].
+#[derive(Clone, Copy)]
+pub struct Many<P>(P);
+
+impl<'a, I: Clone, St, P: ParserExt<'a, I, St>> ParserExt<'a, I, St> for Many<P> {
+    type Output = Vec<P::Output>;
+    type Error = P::Error;
+
+    fn parse_with(
+        &self,
+        input: I,
+        mut at: usize,
+        session: &mut Session<'a, P::Error, St>,
+    ) -> ParseResult<Self::Output, P::Error> {
+        let mut os = Vec::new();
+        loop {
+            let checkpoint = session.checkpoint();
+            match self.0.parse_with(input.clone(), at, session) {
+                Ok((o, rest)) => {
+                    os.push(o);
+                    at = rest;
+                }
+                Err(err) => match err.recover {
+                    Recover::Recoverable => {
+                        session.backtrack(checkpoint, err);
+                        return Ok((os, at));
+                    }
+                    Recover::Fatal | Recover::Incomplete { .. } => return Err(err),
+                },
+            }
+        }
+    }
+}
+
+/// See [`ParserExt::ignore`].
+#[derive(Clone, Copy)]
+pub struct Ignore<P>(P);
+
+impl<'a, I, St, P: ParserExt<'a, I, St>> ParserExt<'a, I, St> for Ignore<P> {
+    type Output = ();
+    type Error = P::Error;
+
+    fn parse_with(
+        &self,
+        input: I,
+        at: usize,
+        session: &mut Session<'a, P::Error, St>,
+    ) -> ParseResult<(), P::Error> {
+        self.0
+            .parse_with(input, at, session)
+            .map(|(_, rest)| ((), rest))
+    }
+}
@@ -0,0 +1,294 @@
+//! Generating random input from a grammar, for fuzzing the code that
+//! consumes its output. See [`generate`] and [`assert_parses`].
+//!
+//! Input is generated from the structure that the combinators recorded, see
+//! [`grammar`](crate::grammar): literals from [`just`](crate::primitive::just),
+//! and the sequences, choices and repetitions around them. Parsers whose
+//! structure is unknown, such as [`unit`](crate::primitive::unit) or a
+//! [`Parser::filter`](crate::Parser::filter), can't be generated, so a
+//! grammar can only be generated as far as it's built from the recorded
+//! combinators.
+//!
+//! Generation is deterministic: the same grammar, seed and depth always give
+//! the same input.
+//!
+//! The structure describes the input that the grammar's EBNF would match,
+//! which the parser itself doesn't always accept: a choice commits to the
+//! first alternative that succeeds, and a repetition consumes as many items
+//! as it can, without trying the rest of the grammar with fewer. So the
+//! parser of `any([just("a"), just("ab")]).left(end())` rejects the `ab` that
+//! may be generated for it, and that of
+//! `just("a").many().then(just("a"))` rejects everything.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use hair::{generate, primitive::{any, just, recursive}, Parser};
+//!
+//! fn list<'a>() -> Parser<'a, &'a str, usize, ()> {
+//!     recursive(|list| {
+//!         let item = any([just("a"), just("b")]).map(|_| 1).or(list);
+//!         item.separate(just(","))
+//!             .surround(just("("), just(")"))
+//!             .map(|items| items.len())
+//!     })
+//! }
+//!
+//! let input = generate::generate(&list().grammar(), 7, 3).unwrap();
+//! assert!(list().parse(&input).is_ok());
+//!
+//! // or, for many seeds at once
+//! generate::assert_parses(|_| list(), 0..100, 3);
+//! ```
+
+use std::{borrow::Borrow, collections::HashMap, fmt::Debug, ops::Range};
+
+use crate::{
+    grammar::{Grammar, Node},
+    sync::Rc,
+    Parser,
+};
+
+/// How many times more than its minimum a repetition may generate its item,
+/// when its maximum allows.
+const MORE: usize = 3;
+
+/// Input that generated pieces can be concatenated into.
+pub trait Concat: ToOwned {
+    fn concat(pieces: &[&Self]) -> Self::Owned;
+}
+
+impl Concat for str {
+    fn concat(pieces: &[&str]) -> String {
+        pieces.concat()
+    }
+}
+
+impl<T: Clone> Concat for [T] {
+    fn concat(pieces: &[&[T]]) -> Vec<T> {
+        pieces.concat()
+    }
+}
+
+/// Generate random input from the structure of `grammar`, from `seed`.
+/// Recursive parsers are entered at most `depth` times deep where possible,
+/// and repetitions only generate their minimum once that is reached; beyond
+/// that, the shortest way out of the recursion is taken.
+///
+/// The parser may still reject the input, see the [module](self)
+/// documentation.
+///
+/// Returns `None` if every way to generate the grammar goes through a parser
+/// whose structure is unknown.
+pub fn generate<'a, S>(grammar: &Grammar<'a, &'a S>, seed: u64, depth: usize) -> Option<S::Owned>
+where
+    S: Concat + ?Sized,
+{
+    let mut generator = Generator {
+        rng: Rng::new(seed),
+        depths: HashMap::new(),
+        pieces: Vec::new(),
+    };
+    generator.measure(&grammar.root);
+    generator.generate(&grammar.root, depth)?;
+    Some(S::concat(&generator.pieces))
+}
+
+/// Assert that the parser built by `grammar` accepts the input generated
+/// from it for each of `seeds`, see [`generate`]. `grammar` is given the
+/// input to build the parser for, like in
+/// [`parse_stream`](crate::stream::parse_stream).
+///
+/// A grammar whose parser rejects some of what its structure describes, see
+/// the [module](self) documentation, fails for the seeds that generate it.
+/// Each input is parsed with the default user state, see
+/// [`Parser::parse_with_state`].
+///
+/// # Panics
+///
+/// Panics if the grammar can't be generated, or with the seed, the input and
+/// the error if parsing fails.
+pub fn assert_parses<S, O, E, St, F>(grammar: F, seeds: Range<u64>, depth: usize)
+where
+    S: Concat + ?Sized,
+    S::Owned: Debug,
+    E: Debug,
+    St: Default,
+    F: for<'b> Fn(&'b S) -> Parser<'b, &'b S, O, E, St>,
+{
+    let empty = S::concat(&[]);
+    for seed in seeds {
+        let input = generate(&grammar(empty.borrow()).grammar(), seed, depth)
+            .expect("the grammar can't be generated, as its structure is unknown");
+        let parser = grammar(input.borrow());
+        if let (Err((err, at)), _) = parser.parse_with_state(input.borrow(), St::default()) {
+            panic!("the input generated from seed {seed}, {input:?}, failed with {err:?} at {at}");
+        }
+    }
+}
+
+struct Generator<'a, I> {
+    rng: Rng,
+    /// The least depth that each recursive parser can be generated in, by
+    /// address, or `None` if it can't be.
+    depths: HashMap<*const Node<'a, I>, Option<usize>>,
+    pieces: Vec<I>,
+}
+
+impl<'a, I> Generator<'a, I> {
+    /// Find the least depth of each recursive parser in `root`, by lowering
+    /// it until none of them change.
+    fn measure(&mut self, root: &Rc<Node<'a, I>>) {
+        let mut knots = Vec::new();
+        find_knots(root, &mut knots);
+        for knot in &knots {
+            self.depths.insert(Rc::as_ptr(knot), None);
+        }
+
+        loop {
+            let mut changed = false;
+            for knot in &knots {
+                let Node::Recursive(body) = &**knot else {
+                    unreachable!("only recursive parsers are knots");
+                };
+                let depth = body.get().and_then(|body| self.depth(body)).map(|d| d + 1);
+                if self.depths.insert(Rc::as_ptr(knot), depth) != Some(depth) {
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+    }
+
+    /// The least depth that `node` can be generated in, or `None` if it
+    /// can't be.
+    fn depth(&self, node: &Rc<Node<'a, I>>) -> Option<usize> {
+        match &**node {
+            Node::Unknown => None,
+            Node::Literal(_) | Node::Separated { .. } => Some(0),
+            Node::Sequence(items) => items
+                .iter()
+                .try_fold(0, |depth, item| Some(depth.max(self.depth(item)?))),
+            Node::Choice(items) => items.iter().filter_map(|item| self.depth(item)).min(),
+            Node::Repeat { item, min, .. } => match min {
+                0 => Some(0),
+                _ => self.depth(item),
+            },
+            Node::Rule(_, body) => self.depth(body),
+            Node::Recursive(_) => self.depths.get(&Rc::as_ptr(node)).copied().flatten(),
+            Node::Recurse(_) => node.resolve().and_then(|knot| self.depth(&knot)),
+        }
+    }
+
+    /// Whether `node` can be generated within `depth`.
+    fn fits(&self, node: &Rc<Node<'a, I>>, depth: usize) -> bool {
+        self.depth(node).is_some_and(|least| least <= depth)
+    }
+
+    fn generate(&mut self, node: &Rc<Node<'a, I>>, depth: usize) -> Option<()> {
+        match &**node {
+            Node::Unknown => return None,
+            Node::Literal(literal) => self.pieces.push(literal()),
+            Node::Sequence(items) => {
+                for item in items {
+                    self.generate(item, depth)?;
+                }
+            }
+            Node::Choice(items) => {
+                let mut choices: Vec<_> =
+                    items.iter().filter(|item| self.fits(item, depth)).collect();
+                if choices.is_empty() {
+                    // the way out of the recursion
+                    let least = items.iter().filter_map(|item| self.depth(item)).min()?;
+                    choices = items
+                        .iter()
+                        .filter(|item| self.depth(item) == Some(least))
+                        .collect();
+                }
+                let choice = choices[self.rng.below(choices.len())];
+                self.generate(choice, depth)?;
+            }
+            Node::Repeat { item, min, max } => {
+                let mut count = *min;
+                if depth > 0 && self.fits(item, depth) {
+                    let more = max.map_or(MORE, |max| max.saturating_sub(*min));
+                    count += self.rng.below(more + 1);
+                }
+                for _ in 0..count {
+                    self.generate(item, depth)?;
+                }
+            }
+            Node::Separated { item, separator } => {
+                if depth > 0 && self.fits(item, depth) && self.fits(separator, depth) {
+                    let count = self.rng.below(MORE + 1);
+                    for i in 0..count {
+                        if i > 0 {
+                            self.generate(separator, depth)?;
+                        }
+                        self.generate(item, depth)?;
+                    }
+                    if count > 0 && self.rng.below(2) == 0 {
+                        self.generate(separator, depth)?;
+                    }
+                }
+            }
+            Node::Rule(_, body) => self.generate(body, depth)?,
+            Node::Recursive(body) => self.generate(body.get()?, depth.saturating_sub(1))?,
+            Node::Recurse(_) => self.generate(&node.resolve()?, depth)?,
+        }
+        Some(())
+    }
+}
+
+/// Collect the recursive parsers in `node`.
+fn find_knots<'a, I>(node: &Rc<Node<'a, I>>, knots: &mut Vec<Rc<Node<'a, I>>>) {
+    match &**node {
+        Node::Unknown | Node::Literal(_) | Node::Recurse(_) => {}
+        Node::Sequence(items) | Node::Choice(items) => {
+            items.iter().for_each(|item| find_knots(item, knots));
+        }
+        Node::Repeat { item, .. } => find_knots(item, knots),
+        Node::Separated { item, separator } => {
+            find_knots(item, knots);
+            find_knots(separator, knots);
+        }
+        Node::Rule(_, body) => find_knots(body, knots),
+        Node::Recursive(body) => {
+            if knots.iter().any(|knot| Rc::ptr_eq(knot, node)) {
+                return;
+            }
+            knots.push(Rc::clone(node));
+            if let Some(body) = body.get() {
+                find_knots(body, knots);
+            }
+        }
+    }
+}
+
+/// A xorshift64* generator, seeded with splitmix64 so that nearby seeds give
+/// unrelated numbers.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        // xorshift never leaves a state of 0
+        Rng((z ^ (z >> 31)).max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// A number below `n`, which must be positive.
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
@@ -0,0 +1,391 @@
+//! The structure of a grammar, as recorded by the combinators that build it,
+//! for documenting it. See [`Parser::grammar`](crate::Parser::grammar).
+//!
+//! Sequences ([`Parser::then`](crate::Parser::then) and the combinators built
+//! on it), choices ([`Parser::or`](crate::Parser::or),
+//! [`any`](crate::primitive::any)), repetitions
+//! ([`Parser::many_with`](crate::Parser::many_with),
+//! [`Parser::separate`](crate::Parser::separate),
+//! [`Parser::optional`](crate::Parser::optional)), literals
+//! ([`just`](crate::primitive::just)) and rules
+//! ([`Parser::labelled`](crate::Parser::labelled),
+//! [`recursive`](crate::primitive::recursive)) are recorded, and
+//! combinators that don't change what a parser accepts, like
+//! [`Parser::map`](crate::Parser::map), keep the structure of the parser
+//! they wrap. Anything else, such as a parser written with
+//! [`Parser::new`](crate::Parser::new) or a
+//! [`Parser::filter`](crate::Parser::filter), is unknown; labelling it gives
+//! it a name at least.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use hair::primitive::{any, just};
+//!
+//! let digit = any([just("0"), just("1")]).labelled("digit");
+//! let number = digit.clone().then(digit.many()).labelled("number");
+//!
+//! assert_eq!(
+//!     number.grammar().to_string(),
+//!     "\
+//! number = digit , { digit } ;
+//! digit = \"0\" | \"1\" ;
+//! "
+//! );
+//! ```
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Debug, Write},
+};
+
+use crate::{
+    sync::{OnceCell, Rc, Weak},
+    trace::write_json_string,
+};
+
+/// Trait object that produces the input of a literal.
+#[cfg(not(feature = "sync"))]
+pub(crate) type LiteralFn<'a, I> = dyn Fn() -> I + 'a;
+
+/// Trait object that produces the input of a literal.
+#[cfg(feature = "sync")]
+pub(crate) type LiteralFn<'a, I> = dyn Fn() -> I + Send + Sync + 'a;
+
+/// The structure of a parser.
+pub(crate) enum Node<'a, I> {
+    /// A parser whose structure isn't recorded.
+    Unknown,
+    /// Input that must match exactly.
+    Literal(Rc<LiteralFn<'a, I>>),
+    Sequence(Vec<Rc<Node<'a, I>>>),
+    Choice(Vec<Rc<Node<'a, I>>>),
+    /// `item` between `min` and `max` times.
+    Repeat {
+        item: Rc<Node<'a, I>>,
+        min: usize,
+        max: Option<usize>,
+    },
+    /// Zero or more `item`s, separated by `separator`, with an optional one
+    /// at the end.
+    Separated {
+        item: Rc<Node<'a, I>>,
+        separator: Rc<Node<'a, I>>,
+    },
+    /// A labelled rule.
+    Rule(&'static str, Rc<Node<'a, I>>),
+    /// A recursive parser, whose body is set once it's built.
+    Recursive(OnceCell<Rc<Node<'a, I>>>),
+    /// The recursive parser that this one stands for, in its body.
+    Recurse(Weak<Node<'a, I>>),
+}
+
+impl<'a, I> Node<'a, I> {
+    /// The recursive parser this node stands for, if it's a
+    /// [`Node::Recurse`], or else the node itself.
+    pub(crate) fn resolve(self: &Rc<Self>) -> Option<Rc<Self>> {
+        match &**self {
+            Node::Recurse(knot) => knot.upgrade(),
+            _ => Some(Rc::clone(self)),
+        }
+    }
+}
+
+/// The grammar of a parser, see [`Parser::grammar`](crate::Parser::grammar).
+///
+/// Its [`Display`](fmt::Display) implementation prints it as EBNF
+/// (ISO/IEC 14977): a rule for each labelled or recursive parser, starting
+/// with the parser itself, or a rule named `grammar` if it's neither.
+/// Literals are printed with their [`Debug`] implementation, and unknown
+/// parsers as `? unknown ?`.
+pub struct Grammar<'a, I> {
+    pub(crate) root: Rc<Node<'a, I>>,
+}
+
+impl<I: Debug> Grammar<'_, I> {
+    /// Export the grammar as JSON, for drawing railroad diagrams: an array of
+    /// rules, starting with the parser itself, each an object with a `name`
+    /// and a `body`. Each expression is an object with a `type`, which is
+    /// one of
+    ///
+    /// - `terminal`, with the [`Debug`] form of the literal as `text`,
+    /// - `nonterminal`, with the `name` of a rule,
+    /// - `sequence` or `choice`, with an array of `items`,
+    /// - `repeat`, with an `item`, a `min` and a `max`, which may be `null`,
+    /// - `separated`, with an `item` and a `separator`,
+    /// - `unknown`.
+    pub fn to_json(&self) -> String {
+        let rules = Rules::collect(&self.root);
+        let mut json = String::from("[");
+        for (i, (name, body)) in rules.rules.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            json.push_str("{\"name\":");
+            write_json_string(&mut json, name);
+            json.push_str(",\"body\":");
+            rules.write_json(&mut json, body);
+            json.push('}');
+        }
+        json.push(']');
+        json
+    }
+}
+
+impl<I: Debug> fmt::Display for Grammar<'_, I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rules = Rules::collect(&self.root);
+        for (name, body) in &rules.rules {
+            let mut ebnf = String::new();
+            rules.write_ebnf(&mut ebnf, body, Precedence::Choice);
+            writeln!(f, "{name} = {ebnf} ;")?;
+        }
+        Ok(())
+    }
+}
+
+/// How tightly an expression binds in EBNF, from loosest to tightest.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Precedence {
+    Choice,
+    Sequence,
+    Primary,
+}
+
+/// The rules of a grammar, and the names of its recursive parsers.
+struct Rules<'a, I> {
+    rules: Vec<(String, Rc<Node<'a, I>>)>,
+    /// The names of recursive parsers, by address.
+    names: HashMap<*const Node<'a, I>, String>,
+    labels: HashSet<&'static str>,
+}
+
+impl<'a, I: Debug> Rules<'a, I> {
+    fn collect(root: &Rc<Node<'a, I>>) -> Self {
+        let mut rules = Rules {
+            rules: Vec::new(),
+            names: HashMap::new(),
+            labels: HashSet::new(),
+        };
+        if !matches!(**root, Node::Rule(..) | Node::Recursive(_)) {
+            rules.rules.push(("grammar".to_string(), Rc::clone(root)));
+        }
+        rules.visit(root);
+        rules
+    }
+
+    /// Add the rules of `node` and the nodes in it, parents first.
+    fn visit(&mut self, node: &Rc<Node<'a, I>>) {
+        match &**node {
+            Node::Unknown | Node::Literal(_) => {}
+            Node::Sequence(items) | Node::Choice(items) => {
+                items.iter().for_each(|item| self.visit(item));
+            }
+            Node::Repeat { item, .. } => self.visit(item),
+            Node::Separated { item, separator } => {
+                self.visit(item);
+                self.visit(separator);
+            }
+            Node::Rule(label, body) => {
+                if self.labels.insert(label) {
+                    self.rules.push((label.to_string(), Rc::clone(body)));
+                    self.visit(body);
+                }
+            }
+            Node::Recursive(body) => {
+                if self.names.contains_key(&Rc::as_ptr(node)) {
+                    return;
+                }
+                let Some(body) = body.get() else {
+                    return;
+                };
+
+                // a labelled body names the rule
+                let name = match &**body {
+                    Node::Rule(label, _) => label.to_string(),
+                    _ => format!("rule{}", self.names.len() + 1),
+                };
+                self.names.insert(Rc::as_ptr(node), name.clone());
+                match &**body {
+                    Node::Rule(..) => self.visit(body),
+                    _ => {
+                        self.rules.push((name, Rc::clone(body)));
+                        self.visit(body);
+                    }
+                }
+            }
+            Node::Recurse(_) => {
+                if let Some(knot) = node.resolve() {
+                    self.visit(&knot);
+                }
+            }
+        }
+    }
+
+    /// The name of the rule that `node` refers to, if it's a rule.
+    fn name(&self, node: &Rc<Node<'a, I>>) -> Option<String> {
+        match &**node {
+            Node::Rule(label, _) => Some(label.to_string()),
+            Node::Recursive(_) => self.names.get(&Rc::as_ptr(node)).cloned(),
+            Node::Recurse(_) => node.resolve().and_then(|knot| self.name(&knot)),
+            _ => None,
+        }
+    }
+
+    fn write_ebnf(&self, ebnf: &mut String, node: &Rc<Node<'a, I>>, precedence: Precedence) {
+        if let Some(name) = self.name(node) {
+            ebnf.push_str(&name);
+            return;
+        }
+
+        let group = |ebnf: &mut String, items: &[&Rc<Node<'a, I>>], separator, inner| {
+            let parenthesize = precedence > inner && items.len() > 1;
+            if parenthesize {
+                ebnf.push_str("( ");
+            }
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    ebnf.push_str(separator);
+                }
+                let precedence = if items.len() > 1 { inner } else { precedence };
+                self.write_ebnf(ebnf, item, precedence);
+            }
+            if parenthesize {
+                ebnf.push_str(" )");
+            }
+        };
+
+        match &**node {
+            Node::Unknown | Node::Recursive(_) | Node::Recurse(_) => ebnf.push_str("? unknown ?"),
+            Node::Literal(literal) => write!(ebnf, "{:?}", literal()).unwrap(),
+            Node::Sequence(_) => group(ebnf, &flatten(node), " , ", Precedence::Sequence),
+            Node::Choice(_) => group(ebnf, &flatten(node), " | ", Precedence::Choice),
+            Node::Repeat { item, min, max } => {
+                // `min` times, then any number of times more, or up to `max`
+                let times = |ebnf: &mut String, times: usize| {
+                    if times > 1 {
+                        write!(ebnf, "{times} * ").unwrap();
+                    }
+                };
+                let more = max.map(|max| max.saturating_sub(*min));
+                let parts = usize::from(*min > 0) + usize::from(more != Some(0));
+                let parenthesize = precedence == Precedence::Primary && parts > 1;
+                if parenthesize {
+                    ebnf.push_str("( ");
+                }
+                if *min > 0 {
+                    times(ebnf, *min);
+                    self.write_ebnf(ebnf, item, Precedence::Primary);
+                }
+                if parts > 1 {
+                    ebnf.push_str(" , ");
+                }
+                match more {
+                    None => {
+                        ebnf.push_str("{ ");
+                        self.write_ebnf(ebnf, item, Precedence::Choice);
+                        ebnf.push_str(" }");
+                    }
+                    Some(0) => {}
+                    Some(more) => {
+                        times(ebnf, more);
+                        ebnf.push_str("[ ");
+                        self.write_ebnf(ebnf, item, Precedence::Choice);
+                        ebnf.push_str(" ]");
+                    }
+                }
+                if parenthesize {
+                    ebnf.push_str(" )");
+                }
+            }
+            Node::Separated { item, separator } => {
+                ebnf.push_str("[ ");
+                self.write_ebnf(ebnf, item, Precedence::Sequence);
+                ebnf.push_str(" , { ");
+                self.write_ebnf(ebnf, separator, Precedence::Sequence);
+                ebnf.push_str(" , ");
+                self.write_ebnf(ebnf, item, Precedence::Sequence);
+                ebnf.push_str(" } , [ ");
+                self.write_ebnf(ebnf, separator, Precedence::Choice);
+                ebnf.push_str(" ] ]");
+            }
+            Node::Rule(..) => unreachable!("rules are referred to by name"),
+        }
+    }
+
+    fn write_json(&self, json: &mut String, node: &Rc<Node<'a, I>>) {
+        if let Some(name) = self.name(node) {
+            json.push_str("{\"type\":\"nonterminal\",\"name\":");
+            write_json_string(json, &name);
+            json.push('}');
+            return;
+        }
+
+        let items = |json: &mut String, kind, items: Vec<&Rc<Node<'a, I>>>| {
+            write!(json, "{{\"type\":\"{kind}\",\"items\":[").unwrap();
+            for (i, item) in items.into_iter().enumerate() {
+                if i > 0 {
+                    json.push(',');
+                }
+                self.write_json(json, item);
+            }
+            json.push_str("]}");
+        };
+
+        match &**node {
+            Node::Unknown | Node::Recursive(_) | Node::Recurse(_) => {
+                json.push_str("{\"type\":\"unknown\"}");
+            }
+            Node::Literal(literal) => {
+                json.push_str("{\"type\":\"terminal\",\"text\":");
+                write_json_string(json, &format!("{:?}", literal()));
+                json.push('}');
+            }
+            Node::Sequence(_) => items(json, "sequence", flatten(node)),
+            Node::Choice(_) => items(json, "choice", flatten(node)),
+            Node::Repeat { item, min, max } => {
+                json.push_str("{\"type\":\"repeat\",\"item\":");
+                self.write_json(json, item);
+                write!(json, ",\"min\":{min},\"max\":").unwrap();
+                match max {
+                    Some(max) => write!(json, "{max}}}").unwrap(),
+                    None => json.push_str("null}"),
+                }
+            }
+            Node::Separated { item, separator } => {
+                json.push_str("{\"type\":\"separated\",\"item\":");
+                self.write_json(json, item);
+                json.push_str(",\"separator\":");
+                self.write_json(json, separator);
+                json.push('}');
+            }
+            Node::Rule(..) => unreachable!("rules are referred to by name"),
+        }
+    }
+}
+
+/// The items of a sequence or choice, with those of nested sequences or
+/// choices of the same kind in their place.
+fn flatten<'n, 'a, I>(node: &'n Rc<Node<'a, I>>) -> Vec<&'n Rc<Node<'a, I>>> {
+    let mut flat = Vec::new();
+    match &**node {
+        Node::Sequence(items) => {
+            for item in items {
+                match &**item {
+                    Node::Sequence(_) => flat.extend(flatten(item)),
+                    _ => flat.push(item),
+                }
+            }
+        }
+        Node::Choice(items) => {
+            for item in items {
+                match &**item {
+                    Node::Choice(_) => flat.extend(flatten(item)),
+                    _ => flat.push(item),
+                }
+            }
+        }
+        _ => flat.push(node),
+    }
+    flat
+}
@@ -0,0 +1,128 @@
+//! Reparsing input after an edit, reusing the results of
+//! [memoized](crate::Parser::memoize) parsers that the edit can't have
+//! changed. See [`Parser::reparse`](crate::Parser::reparse).
+//!
+//! A memoized result is reused if the parser that produced it didn't read
+//! any of the edited input, as marked with
+//! [`Session::read_to`](crate::Session::read_to). Results after the edit
+//! move with it, unless their output holds offsets, e.g. spans from
+//! [`Parser::with_span`](crate::Parser::with_span), as marked with
+//! [`Session::read_offset`](crate::Session::read_offset): those are only
+//! reused before the edit. So are results that read the input before them,
+//! as marked with [`Session::read_behind`](crate::Session::read_behind),
+//! such as those of the [`indent`](crate::indent) parsers. Either way,
+//! reparsing outputs the same as parsing from scratch, and parsers whose
+//! output doesn't depend on its offset, such as [`cst`](crate::cst) nodes,
+//! are reused the most.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+//! use hair::{cst::{node, token}, primitive::just, util::recognize_input};
+//!
+//! #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+//! enum Kind { Word, Space, Sentence }
+//!
+//! static WORDS_PARSED: AtomicUsize = AtomicUsize::new(0);
+//! let word = token(Kind::Word, recognize_input(|c: &char| c.is_alphabetic()))
+//!     .map(|word| {
+//!         WORDS_PARSED.fetch_add(1, Relaxed);
+//!         word
+//!     })
+//!     .memoize();
+//! let space = token(Kind::Space, just(" "));
+//! let sentence = node(Kind::Sentence, word.clone().then(space.then(word).many()));
+//!
+//! let (_, version) = sentence.parse_versioned("one two three");
+//! assert_eq!(WORDS_PARSED.load(Relaxed), 3);
+//!
+//! // replace `two` with `second`
+//! let (tree, _) = sentence.reparse("one second three", version, (4..7, "second"));
+//! assert_eq!(tree, sentence.parse("one second three"));
+//! // only the new word was parsed again, or rather twice, by the full parse
+//! assert_eq!(WORDS_PARSED.load(Relaxed), 7);
+//! ```
+
+use std::ops::Range;
+
+use crate::session::{Memo, MemoKey};
+
+/// A finished parse, which holds on to its memoized results for
+/// [`Parser::reparse`](crate::Parser::reparse) to reuse.
+pub struct Version<'a> {
+    pub(crate) memo: Memo<'a>,
+}
+
+/// A change to the input: the units in `removed` were replaced with
+/// `inserted` units.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Edit {
+    pub removed: Range<usize>,
+    pub inserted: usize,
+}
+
+impl Edit {
+    pub fn new(removed: Range<usize>, inserted: usize) -> Self {
+        Self { removed, inserted }
+    }
+}
+
+/// Replacing a range of a string with the text.
+impl From<(Range<usize>, &str)> for Edit {
+    fn from((removed, text): (Range<usize>, &str)) -> Self {
+        Self::new(removed, text.len())
+    }
+}
+
+/// A previous parse, and the edit since.
+pub(crate) struct Reuse<'a> {
+    pub(crate) memo: Memo<'a>,
+    edit: Edit,
+}
+
+impl<'a> Reuse<'a> {
+    pub(crate) fn new(previous: Version<'a>, edit: Edit) -> Self {
+        Self {
+            memo: previous.memo,
+            edit,
+        }
+    }
+
+    /// The offset in the previous input that `at` corresponds to, and the
+    /// change from it, if `at` is outside of the edit.
+    pub(crate) fn previous_offset(&self, at: usize) -> Option<(usize, isize)> {
+        let Edit { removed, inserted } = &self.edit;
+        if at < removed.start {
+            Some((at, 0))
+        } else if at >= removed.start + inserted {
+            let delta = *inserted as isize - removed.len() as isize;
+            Some((at.wrapping_add_signed(-delta), delta))
+        } else {
+            None
+        }
+    }
+
+    /// The key in the previous parse that `key` corresponds to, and the
+    /// change from its offset, if neither its offset nor the end of its input
+    /// is in the edit.
+    pub(crate) fn previous_key(&self, key: &MemoKey) -> Option<(MemoKey, isize)> {
+        let (at, delta) = self.previous_offset(key.at)?;
+        let end = match key.end {
+            Some(end) => Some(self.previous_offset(end)?.0),
+            None => None,
+        };
+        let key = MemoKey {
+            at,
+            end,
+            indents: key.indents.clone(),
+        };
+        Some((key, delta))
+    }
+
+    /// Whether a result at `previous` that read the input up to `read`, and
+    /// maybe `behind` it, is unaffected by the edit.
+    pub(crate) fn unaffected(&self, previous: usize, read: usize, behind: bool) -> bool {
+        read <= self.edit.removed.start || (!behind && previous >= self.edit.removed.end)
+    }
+}
@@ -0,0 +1,317 @@
+//! Parsers for indentation-sensitive text, over `&str`: blocks whose lines
+//! are indented past the line that introduces them, as in Python or YAML.
+//!
+//! The session keeps a stack of the indentation of the blocks being parsed.
+//! [`indented_block`] pushes onto it, and [`same_indent`] checks lines
+//! against its top; outside of any block, lines are expected to be
+//! unindented. Indentation is the number of spaces and tabs a line starts
+//! with, each counting as one.
+//!
+//! These parsers depend on the blocks around them and the input before them,
+//! not only on the input they read. [Memoized](crate::Parser::memoize)
+//! parsers keep their results apart by the blocks they're parsed in, and
+//! those built on these parsers are only reused by
+//! [`Parser::reparse`](crate::Parser::reparse) before the edit, see
+//! [`Session::read_behind`].
+//!
+//! ## Example
+//!
+//! ```rust
+//! use hair::{
+//!     indent::{indented_block, IndentError},
+//!     primitive::{end, just, recursive},
+//!     util::recognize_input,
+//! };
+//!
+//! #[derive(Clone, Debug, Default, PartialEq)]
+//! enum Error {
+//!     Indent(IndentError),
+//!     #[default]
+//!     Unexpected,
+//! }
+//!
+//! impl From<IndentError> for Error {
+//!     fn from(err: IndentError) -> Self {
+//!         Error::Indent(err)
+//!     }
+//! }
+//!
+//! // a name, and the names indented under it
+//! #[derive(Clone, Debug, PartialEq)]
+//! struct Tree(String, Vec<Tree>);
+//!
+//! let tree = recursive(|tree| {
+//!     let line_end = just("\n").map(|_| ()).or(end()).map_err(|_| Error::Unexpected);
+//!     recognize_input(|c: &char| c.is_alphanumeric())
+//!         .map(String::from)
+//!         .map_err(|_| Error::Unexpected)
+//!         .left(line_end)
+//!         .then(indented_block(tree).optional())
+//!         .map(|(name, children)| Tree(name, children.unwrap_or_default()))
+//! });
+//!
+//! let source = "\
+//! a
+//!   b
+//!     c
+//!   d
+//! ";
+//! assert_eq!(
+//!     tree.parse(source),
+//!     Ok(Tree(
+//!         "a".to_string(),
+//!         vec![
+//!             Tree("b".to_string(), vec![Tree("c".to_string(), vec![])]),
+//!             Tree("d".to_string(), vec![]),
+//!         ]
+//!     ))
+//! );
+//! ```
+
+use crate::{Error, Parser, Session};
+
+/// The error of the parsers in this module.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndentError {
+    /// The parser isn't at the start of a line.
+    NotLineStart,
+    /// The first line of a block isn't indented past the block around it.
+    ExpectedIndent { found: usize },
+    /// A line isn't indented like the rest of its block.
+    Misaligned { expected: usize, found: usize },
+    /// A line ends a block, but isn't indented like any of the blocks around
+    /// it. This error is fatal.
+    UnmatchedDedent { found: usize },
+}
+
+/// Succeed at the start of a line, i.e. at the start of the input or after a
+/// `\n`, consuming nothing.
+pub fn line_start<'a, E, St>() -> Parser<'a, &'a str, (), E, St>
+where
+    E: From<IndentError> + 'a,
+    St: 'a,
+{
+    Parser::new(|input: &'a str, at, session| {
+        if is_line_start(input, at, session) {
+            Ok(((), at))
+        } else {
+            Err(Error::new(IndentError::NotLineStart.into(), at))
+        }
+    })
+}
+
+/// At the start of a line, consume its indentation if it's that of the block
+/// being parsed, see [`indented_block`].
+pub fn same_indent<'a, E, St>() -> Parser<'a, &'a str, (), E, St>
+where
+    E: From<IndentError> + 'a,
+    St: 'a,
+{
+    Parser::new(|input: &'a str, at, session| {
+        if !is_line_start(input, at, session) {
+            return Err(Error::new(IndentError::NotLineStart.into(), at));
+        }
+
+        let expected = session.indent();
+        let found = indentation(input, at, session);
+        if found == expected {
+            Ok(((), at + found))
+        } else {
+            Err(Error::new(
+                IndentError::Misaligned { expected, found }.into(),
+                at + found,
+            ))
+        }
+    })
+}
+
+/// Parse a block of one or more `item`s, starting at the start of a line.
+/// The block is indented past the block around it, and each item starts on
+/// a line of its own, indented like the first, after any blank lines.
+/// Items are parsed after their line's indentation, and should consume the
+/// line break that ends them.
+///
+/// The block ends at the first line that is indented less, which must be
+/// indented like one of the blocks around it, or the block fails with
+/// [`IndentError::UnmatchedDedent`]. It also ends after an item that stops
+/// short of the end of its line, and at the end of the input, which in a
+/// [stream](crate::stream) it waits for.
+pub fn indented_block<'a, O, E, St>(
+    item: Parser<'a, &'a str, O, E, St>,
+) -> Parser<'a, &'a str, Vec<O>, E, St>
+where
+    O: 'a,
+    E: From<IndentError> + 'a,
+    St: 'a,
+{
+    Parser::new(move |input: &'a str, start, session| {
+        if !is_line_start(input, start, session) {
+            return Err(Error::new(IndentError::NotLineStart.into(), start));
+        }
+
+        let at = skip_blank_lines(input, start, session);
+        let found = indentation(input, at, session);
+        if at == input.len() && session.is_partial() {
+            let err = IndentError::ExpectedIndent { found }.into();
+            return Err(session.out_of_input(err, at, 1));
+        }
+        if found <= session.indent() || at == input.len() {
+            return Err(Error::new(
+                IndentError::ExpectedIndent { found }.into(),
+                at + found,
+            ));
+        }
+
+        session.push_indent(found);
+        let result = block(&item, input, at, found, session);
+        session.pop_indent();
+        result
+    })
+}
+
+/// The items of a block indented by `level`, the first at `at`.
+fn block<'a, O: 'a, E: From<IndentError> + 'a, St: 'a>(
+    item: &Parser<'a, &'a str, O, E, St>,
+    input: &'a str,
+    mut at: usize,
+    level: usize,
+    session: &mut Session<'a, E, St>,
+) -> Result<(Vec<O>, usize), Error<E>> {
+    let mut items = Vec::new();
+    loop {
+        let (o, end) = item.parse_with(input, at + level, session)?;
+        items.push(o);
+        if !is_line_start(input, end, session) {
+            return Ok((items, end));
+        }
+
+        // the next line may yet be indented like this block, or past it
+        let next = skip_blank_lines(input, end, session);
+        if next == input.len() && session.is_partial() {
+            let err = IndentError::Misaligned {
+                expected: level,
+                found: 0,
+            };
+            return Err(session.out_of_input(err.into(), next, 1));
+        }
+        if next == input.len() {
+            return Ok((items, end));
+        }
+
+        let found = indentation(input, next, session);
+        if found == level {
+            at = next;
+        } else if found > level {
+            return Err(Error::new(
+                IndentError::Misaligned {
+                    expected: level,
+                    found,
+                }
+                .into(),
+                next + found,
+            ));
+        } else if found == 0 || session.indents().contains(&found) {
+            return Ok((items, end));
+        } else {
+            return Err(
+                Error::new(IndentError::UnmatchedDedent { found }.into(), next + found).fail(),
+            );
+        }
+    }
+}
+
+/// Run `parser` on the input up to the first line, after the one it starts
+/// on, that isn't indented past the column it starts at (the offside rule).
+/// Blank lines don't end the input. This suits constructs that may continue
+/// on more indented lines, such as a long value in YAML.
+///
+/// # Example
+///
+/// ```rust
+/// use hair::{indent::offside, primitive::unit};
+///
+/// let value = offside(unit::<str>().many()).input();
+/// let source = "one\n  two\nthree";
+/// assert_eq!(value.parse_at(source, 0), Ok(("one\n  two\n", 10)));
+/// ```
+pub fn offside<'a, O, E, St>(parser: Parser<'a, &'a str, O, E, St>) -> Parser<'a, &'a str, O, E, St>
+where
+    O: 'a,
+    E: 'a,
+    St: 'a,
+{
+    Parser::new(move |input: &'a str, at, session| {
+        session.read_behind();
+        let column = at - input[..at].rfind('\n').map_or(0, |i| i + 1);
+        let mut end = next_line(input, at, session);
+        while end < input.len() {
+            let found = indentation(input, end, session);
+            if found <= column && blank_line_end(input, end, session).is_none() {
+                break;
+            }
+            end = next_line(input, end, session);
+        }
+
+        // a line that isn't indented enough ends the input for certain, even
+        // in a stream
+        if end < input.len() {
+            session.truncated(end, |session| parser.parse_with(&input[..end], at, session))
+        } else {
+            parser.parse_with(&input[..end], at, session)
+        }
+    })
+}
+
+/// Whether `at` is at the start of a line, which depends on the input before
+/// it, and on the blocks around it for the parsers that call this.
+fn is_line_start<E, St>(input: &str, at: usize, session: &mut Session<E, St>) -> bool {
+    session.read_behind();
+    at == 0 || input[..at].ends_with('\n')
+}
+
+/// The start of the line after the one `at` is on, or the end of the input.
+fn next_line<E, St>(input: &str, at: usize, session: &mut Session<E, St>) -> usize {
+    match input[at..].find('\n') {
+        Some(i) => {
+            session.read_to(at + i + 1);
+            at + i + 1
+        }
+        None => {
+            session.read_to(input.len() + 1);
+            input.len()
+        }
+    }
+}
+
+/// The indentation of the line starting at `at`.
+fn indentation<E, St>(input: &str, at: usize, session: &mut Session<E, St>) -> usize {
+    let found = input[at..]
+        .bytes()
+        .take_while(|&b| b == b' ' || b == b'\t')
+        .count();
+    session.read_to(at + found + 1);
+    found
+}
+
+/// If the line starting at `at` is blank, the start of the next line.
+fn blank_line_end<E, St>(input: &str, at: usize, session: &mut Session<E, St>) -> Option<usize> {
+    let rest = input[at..].trim_start_matches([' ', '\t']);
+    // the line break, or whatever isn't one, and the `\n` of a `\r\n`
+    session.read_to(input.len() - rest.len() + 2);
+    let rest = match rest.strip_prefix('\n') {
+        Some(rest) => rest,
+        None if rest.is_empty() => rest,
+        None => rest.strip_prefix("\r\n")?,
+    };
+    Some(input.len() - rest.len())
+}
+
+fn skip_blank_lines<E, St>(input: &str, mut at: usize, session: &mut Session<E, St>) -> usize {
+    while at < input.len() {
+        match blank_line_end(input, at, session) {
+            Some(next) => at = next,
+            None => break,
+        }
+    }
+    at
+}
@@ -0,0 +1,199 @@
+//! Splitting source text into tokens with hair parsers, for parsers over
+//! `&[Spanned<Token>]`. See [`lexer`] and [`token`].
+//!
+//! ## Example
+//!
+//! ```rust
+//! use hair::{
+//!     lexer::{lexer, rule, skip, token},
+//!     primitive::just,
+//!     text,
+//!     util::recognize_input,
+//! };
+//!
+//! #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+//! enum Kind {
+//!     Let,
+//!     Identifier,
+//!     Equals,
+//!     Number,
+//! }
+//!
+//! let lex = lexer([
+//!     skip(recognize_input(|c: &char| c.is_whitespace())),
+//!     // `let` is also an identifier, but takes priority as one
+//!     rule(Kind::Let, 1, just("let")),
+//!     rule(Kind::Identifier, 0, text::identifier()),
+//!     rule(Kind::Equals, 0, just("=")),
+//!     rule(Kind::Number, 0, text::integer::<u64>()),
+//! ]);
+//! let tokens = lex.parse("let letter = 1").unwrap();
+//! let missing_name = lex.parse("let = 1").unwrap();
+//!
+//! let statement = token(Kind::Let)
+//!     .right(token(Kind::Identifier))
+//!     .left(token(Kind::Equals))
+//!     .then(token(Kind::Number));
+//! let (name, value) = statement.parse(&tokens).unwrap();
+//! assert_eq!((name.text, name.span()), ("letter", 4..10));
+//! assert_eq!(value.text, "1");
+//!
+//! // errors carry the span of the offending token in the source
+//! let (err, _) = statement.parse(&missing_name).unwrap_err();
+//! assert_eq!((err.found, err.span), (Some(Kind::Equals), 4..5));
+//! ```
+
+use std::ops::Range;
+
+use crate::{Error, MaybeSync, Parser, Recover, Spanned};
+
+/// A token of some kind `K`, and the text it was lexed from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Token<'a, K> {
+    pub kind: K,
+    pub text: &'a str,
+}
+
+/// A way of lexing a token, or input to skip. See [`rule`] and [`skip`].
+pub struct Rule<'a, K, St = ()> {
+    parser: Parser<'a, &'a str, (), (), St>,
+    /// `None` for input that is skipped.
+    kind: Option<K>,
+    priority: i32,
+}
+
+/// Lex a token of `kind` from the input that `parser` consumes. When several
+/// rules match, the longest match wins, and of those, the one of the highest
+/// `priority`.
+pub fn rule<'a, K, O, E, St>(
+    kind: K,
+    priority: i32,
+    parser: Parser<'a, &'a str, O, E, St>,
+) -> Rule<'a, K, St>
+where
+    O: 'a,
+    E: 'a,
+    St: 'a,
+{
+    Rule {
+        parser: parser.ignore().map_err(|_| ()),
+        kind: Some(kind),
+        priority,
+    }
+}
+
+/// Skip the input that `parser` consumes, such as whitespace or comments. It
+/// competes with other rules like a [`rule`] of priority 0.
+pub fn skip<'a, K, O, E, St>(parser: Parser<'a, &'a str, O, E, St>) -> Rule<'a, K, St>
+where
+    O: 'a,
+    E: 'a,
+    St: 'a,
+{
+    Rule {
+        parser: parser.ignore().map_err(|_| ()),
+        kind: None,
+        priority: 0,
+    }
+}
+
+/// Lex the whole input with `rules`, outputting its tokens. At each offset,
+/// the rule that matches the longest input is used; ties go to the rule of
+/// the highest priority, then to the one given first. Rules that match no
+/// input are ignored.
+///
+/// Fails where no rule matches.
+pub fn lexer<'a, K, St, Rules>(
+    rules: Rules,
+) -> Parser<'a, &'a str, Vec<Spanned<Token<'a, K>>>, (), St>
+where
+    K: Clone + 'a,
+    St: 'a,
+    Rules: AsRef<[Rule<'a, K, St>]> + MaybeSync + 'a,
+{
+    Parser::new(move |input: &'a str, mut at, session| {
+        let mut tokens = Vec::new();
+        while at < input.len() {
+            let mut longest: Option<(&Rule<K, St>, usize)> = None;
+            for rule in rules.as_ref() {
+                // rules that don't match aren't errors in the input
+                let end =
+                    match session.scratch(|session| rule.parser.parse_with(input, at, session)) {
+                        Ok((_, end)) => end,
+                        Err(err) if err.recover == Recover::Recoverable => continue,
+                        Err(err) => return Err(err),
+                    };
+                if end > at
+                    && longest.is_none_or(|(longest, longest_end)| {
+                        (end, rule.priority) > (longest_end, longest.priority)
+                    })
+                {
+                    longest = Some((rule, end));
+                }
+            }
+
+            let (rule, end) = longest.ok_or(Error::new((), at))?;
+            if let Some(kind) = &rule.kind {
+                let token = Token {
+                    kind: kind.clone(),
+                    text: &input[at..end],
+                };
+                tokens.push(Spanned::new(token, at..end));
+            }
+            at = end;
+        }
+
+        session.read_to(input.len() + 1);
+        session.read_offset();
+        Ok((tokens, at))
+    })
+}
+
+/// The input of parsers of tokens, as output by [`lexer`].
+pub type Tokens<'a, K> = [Spanned<Token<'a, K>>];
+
+/// The error of [`token`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Unexpected<K> {
+    pub expected: K,
+    /// The kind of the token found instead, or `None` at the end of the
+    /// tokens.
+    pub found: Option<K>,
+    /// The span of the token found in the source, or an empty span after the
+    /// last token.
+    pub span: Range<usize>,
+}
+
+/// Parse a token of `kind`.
+pub fn token<'a, K, St>(
+    kind: K,
+) -> Parser<'a, &'a Tokens<'a, K>, &'a Spanned<Token<'a, K>>, Unexpected<K>, St>
+where
+    K: Clone + PartialEq + MaybeSync + 'a,
+    St: 'a,
+{
+    Parser::new(move |input: &'a Tokens<'a, K>, at, session| {
+        // tokens and errors alike hold spans in the source
+        session.read_offset();
+        match input.get(at) {
+            Some(token) if token.kind == kind => Ok((token, at + 1)),
+            Some(token) => Err(Error::new(
+                Unexpected {
+                    expected: kind.clone(),
+                    found: Some(token.kind.clone()),
+                    span: token.span(),
+                },
+                at,
+            )),
+            None => {
+                let end = input.last().map_or(0, |token| token.span().end);
+                let err = Unexpected {
+                    expected: kind.clone(),
+                    found: None,
+                    span: end..end,
+                };
+                Err(session.out_of_input(err, at, 1))
+            }
+        }
+    })
+}
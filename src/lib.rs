@@ -1,89 +1,333 @@
 #![doc = include_str!("../README.md")]
 
+pub mod binary;
+pub mod cst;
+pub mod error;
+pub mod ext;
+pub mod generate;
+pub mod grammar;
+pub mod incremental;
+pub mod indent;
+pub mod lexer;
+pub mod location;
+pub mod pratt;
 pub mod primitive;
+pub mod recovery;
+pub mod report;
+pub mod session;
 pub mod slice;
+pub mod span;
+pub mod stream;
+mod sync;
+pub mod text;
+pub mod trace;
 pub mod util;
 
 #[cfg(test)]
 mod test;
 
-use std::{ops::Range, rc::Rc};
+use std::ops::Range;
 
+use error::{Context, Merge};
+use grammar::{Grammar, Node};
+use incremental::{Edit, Reuse, Version};
+use location::{LineIndex, Location};
+use recovery::Strategy;
+use session::{MemoId, MemoKey, Records};
+use sync::Rc;
+use trace::{Outcome, Trace, Tracer};
+
+pub use error::{Error, Label, Recover};
+pub use session::Session;
 pub use slice::Slice;
+pub use span::Spanned;
+pub use sync::MaybeSync;
 
 pub type ParseResult<O, E> = Result<(O, usize), Error<E>>;
 
 /// Trait object of a parsing function.
-pub type ParseFn<'a, I, O, E> = dyn Fn(I, usize) -> ParseResult<O, E> + 'a;
+#[cfg(not(feature = "sync"))]
+pub type ParseFn<'a, I, O, E, St = ()> =
+    dyn Fn(I, usize, &mut Session<'a, E, St>) -> ParseResult<O, E> + 'a;
+
+/// Trait object of a parsing function.
+#[cfg(feature = "sync")]
+pub type ParseFn<'a, I, O, E, St = ()> =
+    dyn Fn(I, usize, &mut Session<'a, E, St>) -> ParseResult<O, E> + Send + Sync + 'a;
 
 /// The type of any parser, a wrapper for a [`ParseFn`] object.
 /// To run the parser, call [`Parser::parse`].
+///
+/// With the `sync` feature, parsers are reference counted atomically, and are
+/// `Send + Sync`, so a grammar can be built once and shared between threads.
+/// In exchange, the closures given to combinators must be `Send + Sync`, see
+/// [`MaybeSync`].
+///
+/// Parsers may use a user state of type `St` while they parse, see
+/// [`Session::state`].
 #[must_use = "parsers are lazy; call `Parser::parse` to use them"]
-pub struct Parser<'a, I, O, E> {
-    run: Rc<ParseFn<'a, I, O, E>>,
+pub struct Parser<'a, I, O, E, St = ()> {
+    run: Rc<ParseFn<'a, I, O, E, St>>,
+    /// See [`Parser::grammar`]. Parsers of unknown structure, as built with
+    /// [`Parser::new`], have none, so they don't allocate a node for it.
+    grammar: Option<Rc<Node<'a, I>>>,
 }
 
-impl<'a, I, O, E> Clone for Parser<'a, I, O, E> {
+impl<'a, I, O, E, St> Clone for Parser<'a, I, O, E, St> {
     fn clone(&self) -> Self {
         Self {
             run: Rc::clone(&self.run),
+            grammar: self.grammar.clone(),
         }
     }
 }
 
-impl<'a, I: Clone + 'a, O: 'a, E: 'a> Parser<'a, I, O, E> {
+impl<'a, I: Clone + 'a, O: 'a, E: 'a, St: 'a> Parser<'a, I, O, E, St> {
     pub fn new<P>(p: P) -> Self
     where
-        P: Fn(I, usize) -> ParseResult<O, E> + 'a,
+        P: Fn(I, usize, &mut Session<'a, E, St>) -> ParseResult<O, E> + MaybeSync + 'a,
     {
-        Parser { run: Rc::new(p) }
+        Parser {
+            run: Rc::new(p),
+            grammar: None,
+        }
     }
 
-    /// Parse starting at an offset. This should be used when calling a parser
-    /// inside another parser.
-    pub fn parse_at(&self, i: I, n: usize) -> ParseResult<O, E> {
-        (self.run)(i, n)
+    /// The structure of this parser, as far as the combinators that built it
+    /// recorded it. See [`grammar`].
+    pub fn grammar(&self) -> Grammar<'a, I> {
+        Grammar { root: self.node() }
     }
 
-    /// Parse from the beginning, and collect the output.
-    pub fn parse(&self, i: I) -> Result<O, (E, usize)> {
-        self.parse_at(i, 0)
-            .map(|(o, _)| o)
-            .map_err(|Error { inner, at, .. }| (inner, at))
+    /// The root of this parser's structure, e.g. to build the structure of a
+    /// parser that wraps it.
+    pub(crate) fn node(&self) -> Rc<Node<'a, I>> {
+        self.grammar
+            .clone()
+            .unwrap_or_else(|| Rc::new(Node::Unknown))
+    }
+
+    /// Give this parser the structure `grammar`, e.g. that of the parser it
+    /// wraps.
+    pub(crate) fn with_grammar(self, grammar: impl Into<Option<Rc<Node<'a, I>>>>) -> Self {
+        Parser {
+            grammar: grammar.into(),
+            ..self
+        }
+    }
+
+    /// Parse starting at an offset, within an existing session. This should be
+    /// used when calling a parser inside another parser.
+    pub fn parse_with(
+        &self,
+        i: I,
+        n: usize,
+        session: &mut Session<'a, E, St>,
+    ) -> ParseResult<O, E> {
+        (self.run)(i, n, session)
+    }
+
+    /// Like [`Parser::parse`], but with `state` as the user state of the
+    /// parse, which parsers can read and change, see [`Session::state`].
+    /// Returns whatever state the parse ends with alongside the result.
+    ///
+    /// The state's type is part of the parser's, so a parser can only be
+    /// given state of the type it expects:
+    ///
+    /// ```rust,compile_fail
+    /// use hair::primitive::get_state;
+    ///
+    /// let count = get_state::<u32, &str, ()>();
+    /// count.parse_with_state("", String::new());
+    /// ```
+    pub fn parse_with_state(&self, i: I, state: St) -> (Result<O, (E, usize)>, St) {
+        let mut session = Session::with_state(state);
+        let result = self.parse_with(i, 0, &mut session);
+        let state = session.take_state();
+
+        (output(session.finish_furthest(result)), state)
+    }
+
+    /// Like [`Parser::parse_recovering`], but with `state` as the user state
+    /// of the parse, which is returned last, see [`Parser::parse_with_state`].
+    pub fn parse_recovering_with_state(&self, i: I, state: St) -> (Option<O>, Vec<Error<E>>, St) {
+        let mut session = Session::with_state(state);
+        let result = self.parse_with(i, 0, &mut session);
+        let state = session.take_state();
+
+        let (o, errors) = session.finish_recovering(result);
+        (o, errors, state)
+    }
+
+    /// Like [`Parser::parse_traced`], but with `state` as the user state of
+    /// the parse, which is returned last, see [`Parser::parse_with_state`].
+    pub fn parse_traced_with_state(&self, i: I, state: St) -> (Result<O, (E, usize)>, Trace, St) {
+        let mut session = Session::with_state(state);
+        session.set_tracer(Some(Tracer::default()));
+        let result = self.parse_with(i, 0, &mut session);
+        let trace = session.take_tracer().unwrap().finish();
+        let state = session.take_state();
+
+        (output(session.finish_furthest(result)), trace, state)
+    }
+
+    /// Like [`Parser::parse_versioned`], but with `state` as the user state
+    /// of the parse, which is returned last, see [`Parser::parse_with_state`].
+    pub fn parse_versioned_with_state(
+        &self,
+        i: I,
+        state: St,
+    ) -> (Result<O, (E, usize)>, Version<'a>, St) {
+        self.parse_reusing(i, None, state)
+    }
+
+    /// Like [`Parser::reparse`], but with `state` as the user state of the
+    /// parse, which is returned last, see [`Parser::parse_with_state`].
+    ///
+    /// Reused results don't change the state again, as with
+    /// [`Parser::map_with_state`], so the state should be that of the
+    /// previous parse.
+    pub fn reparse_with_state(
+        &self,
+        i: I,
+        previous: Version<'a>,
+        edit: impl Into<Edit>,
+        state: St,
+    ) -> (Result<O, (E, usize)>, Version<'a>, St) {
+        self.parse_reusing(i, Some(Reuse::new(previous, edit.into())), state)
+    }
+
+    fn parse_reusing(
+        &self,
+        i: I,
+        reuse: Option<Reuse<'a>>,
+        state: St,
+    ) -> (Result<O, (E, usize)>, Version<'a>, St) {
+        let mut session = Session::with_state(state);
+        session.set_reuse(reuse);
+        let result = self.parse_with(i, 0, &mut session);
+        let version = Version {
+            memo: session.take_memo(),
+        };
+        let state = session.take_state();
+
+        (output(session.finish_furthest(result)), version, state)
+    }
+
+    /// On a fatal error, try to recover with `strategy`, which may skip some
+    /// input and produce an output in place of this parser's. The error is
+    /// recorded in the session, and parsing goes on. See [`recovery`].
+    pub fn recover_with(self, strategy: Strategy<'a, I, O, E, St>) -> Parser<'a, I, O, E, St> {
+        let grammar = self.grammar.clone();
+        Parser::new(move |input: I, at, session| {
+            let checkpoint = session.checkpoint();
+            match self.parse_with(input.clone(), at, session) {
+                Err(err) if err.recover == Recover::Fatal => {
+                    session.rewind(checkpoint);
+                    match strategy.recover(&self, input, at, &err, session) {
+                        Some(ok) => {
+                            session.recovered(err);
+                            Ok(ok)
+                        }
+                        None => Err(err),
+                    }
+                }
+                result => result,
+            }
+        })
+        .with_grammar(grammar)
     }
 
     /// Map the parser's output, i.e. turn a `Parser<I, O, E>` into a `Parser<I, O1, E>`.
-    pub fn map<O1: 'a, F>(self, f: F) -> Parser<'a, I, O1, E>
+    pub fn map<O1: 'a, F>(self, f: F) -> Parser<'a, I, O1, E, St>
+    where
+        F: Fn(O) -> O1 + MaybeSync + 'a,
+    {
+        let grammar = self.grammar.clone();
+        Parser::new(move |input, at, session| {
+            self.parse_with(input, at, session)
+                .map(|(o, rest)| (f(o), rest))
+        })
+        .with_grammar(grammar)
+    }
+
+    /// Map the parser's output together with the user state of the parse,
+    /// see [`Session::state`].
+    ///
+    /// Changes to the state aren't undone when the parser is backtracked
+    /// over, unless it's within [`Parser::with_state_scope`]; nor are they
+    /// made again when a [memoized](Parser::memoize) result is reused.
+    pub fn map_with_state<O1: 'a, F>(self, f: F) -> Parser<'a, I, O1, E, St>
+    where
+        F: Fn(O, &mut St) -> O1 + MaybeSync + 'a,
+    {
+        let grammar = self.grammar.clone();
+        Parser::new(move |input, at, session| {
+            let (o, rest) = self.parse_with(input, at, session)?;
+            Ok((f(o, session.state()), rest))
+        })
+        .with_grammar(grammar)
+    }
+
+    /// Restore the user state of the parse to what it was before this parser
+    /// ran, if it fails, or if it succeeds and is then backtracked over.
+    /// Alternatives that are backtracked over then leave no trace in the
+    /// state. See [`Session::state`].
+    ///
+    /// Until the parse ends or backtracks past it, each success keeps a copy
+    /// of the state it started with in the session.
+    pub fn with_state_scope(self) -> Parser<'a, I, O, E, St>
     where
-        F: Fn(O) -> O1 + 'a,
+        St: Clone,
     {
-        Parser::new(move |input, at| self.parse_at(input, at).map(|(o, rest)| (f(o), rest)))
+        let grammar = self.grammar.clone();
+        Parser::new(move |input, at, session: &mut Session<'a, E, St>| {
+            let before = session.state().clone();
+            let checkpoint = session.checkpoint();
+            let result = self.parse_with(input, at, session);
+            session.end_scope(checkpoint, before, result.is_ok());
+            result
+        })
+        .with_grammar(grammar)
     }
 
     /// Map the parser's error, if any, i.e. turn a `Parser<I, O, E>` into a `Parser<I, O, E1>`.
-    pub fn map_err<E1: 'a, F>(self, f: F) -> Parser<'a, I, O, E1>
+    ///
+    /// `f` also maps the errors that the parser recorded in the session while
+    /// backtracking, as they are recorded, even if they are never reported.
+    /// So it runs on the errors of parsers that can't fail, like
+    /// [`Parser::optional`], and should return an error for them rather
+    /// than panic:
+    ///
+    /// ```rust
+    /// use hair::primitive::just;
+    ///
+    /// // `unreachable!()` would panic on the missing " " that is backtracked over
+    /// let ws = just(" ").optional().map_err(|_| "whitespace");
+    /// assert_eq!(ws.parse("x"), Ok(None));
+    /// ```
+    pub fn map_err<E1: 'a, F>(self, f: F) -> Parser<'a, I, O, E1, St>
     where
-        F: Fn(E) -> E1 + 'a,
+        F: Fn(E) -> E1 + MaybeSync + 'a,
     {
-        Parser::new(move |input, at| {
-            self.parse_at(input, at)
-                .map_err(|Error { inner, recover, at }| Error {
-                    inner: f(inner),
-                    recover,
-                    at,
-                })
+        let grammar = self.grammar.clone();
+        Parser::new(move |input, at, session| {
+            session
+                .nest(&f, |session| self.parse_with(input, at, session))
+                .map_err(|err| err.map(&f))
         })
+        .with_grammar(grammar)
     }
 
     /// Map the parser's output to construct a second parser using the output of
     /// the first.
-    pub fn flat_map<O1: 'a, F>(self, f: F) -> Parser<'a, I, O1, E>
+    pub fn flat_map<O1: 'a, F>(self, f: F) -> Parser<'a, I, O1, E, St>
     where
-        F: Fn(O) -> Parser<'a, I, O1, E> + 'a,
+        F: Fn(O) -> Parser<'a, I, O1, E, St> + MaybeSync + 'a,
     {
-        Parser::new(move |input: I, at| {
-            let (o, at) = self.parse_at(input.clone(), at)?;
-            f(o).parse_at(input, at)
+        Parser::new(move |input: I, at, session| {
+            let (o, at) = self.parse_with(input.clone(), at, session)?;
+            f(o).parse_with(input, at, session)
         })
     }
 
@@ -104,109 +348,242 @@ impl<'a, I: Clone + 'a, O: 'a, E: 'a> Parser<'a, I, O, E> {
     /// ```
     ///
     /// Where `identifier` and `value` are user-defined parsers.
-    pub fn expect(self) -> Parser<'a, I, O, E> {
-        Parser::new(move |input, at| match self.parse_at(input, at) {
-            o @ Ok(_) => o,
-            Err(err) => Err(err.fail()),
-        })
+    pub fn expect(self) -> Parser<'a, I, O, E, St> {
+        let grammar = self.grammar.clone();
+        Parser::new(
+            move |input, at, session| match self.parse_with(input, at, session) {
+                o @ Ok(_) => o,
+                Err(err) => Err(err.fail()),
+            },
+        )
+        .with_grammar(grammar)
     }
 
     /// Make a parser fail if its output does not satisfy `predicate`.
-    pub fn filter<P>(self, predicate: P) -> Parser<'a, I, O, ()>
+    pub fn filter<P>(self, predicate: P) -> Parser<'a, I, O, (), St>
     where
-        P: Fn(&O) -> bool + 'a,
+        P: Fn(&O) -> bool + MaybeSync + 'a,
     {
-        Parser::new(move |input, at| match self.parse_at(input, at) {
-            Ok((o, rest)) if predicate(&o) => Ok((o, rest)),
-            Ok(_) | Err(_) => Err(Error {
-                inner: (),
-                recover: Recover::Recoverable,
-                at,
-            }),
+        Parser::new(move |input, at, session| {
+            let err = match session.nest(|_| (), |session| self.parse_with(input, at, session)) {
+                Ok((o, rest)) if predicate(&o) => return Ok((o, rest)),
+                Ok(_) => None,
+                Err(err) => Some(err.map(|_| ())),
+            };
+
+            Err(session.reject(err, (), at))
         })
     }
 
     /// Filter and map. Succeeds only if the predicate returns `Some`.
-    pub fn filter_map<P, O1: 'a>(self, predicate: P) -> Parser<'a, I, O1, ()>
+    pub fn filter_map<P, O1: 'a>(self, predicate: P) -> Parser<'a, I, O1, (), St>
     where
-        P: Fn(O) -> Option<O1> + 'a,
+        P: Fn(O) -> Option<O1> + MaybeSync + 'a,
     {
-        Parser::new(move |input, at| {
-            let (o, rest) = self.clone().map_err(|_| ()).parse_at(input, at)?;
+        Parser::new(move |input, at, session| {
+            let err = match session.nest(|_| (), |session| self.parse_with(input, at, session)) {
+                Ok((o, rest)) => match predicate(o) {
+                    Some(o) => return Ok((o, rest)),
+                    None => None,
+                },
+                Err(err) => Some(err.map(|_| ())),
+            };
+
+            Err(session.reject(err, (), at))
+        })
+    }
 
-            predicate(o).map(|o| (o, rest)).ok_or(Error {
-                inner: (),
-                recover: Recover::Recoverable,
-                at,
-            })
+    /// Cache this parser's results by offset, so that running it again at the
+    /// same offset within a parse, e.g. after backtracking, returns the result
+    /// of the first run rather than parsing again (packrat parsing). The
+    /// errors it recorded in the session are replayed as well.
+    ///
+    /// Runs inside input cut short, as by
+    /// [`length_prefixed`](binary::length_prefixed), or inside the blocks of
+    /// [`indent`], are cached apart from those outside of them.
+    ///
+    /// The results are kept in the [`Session`], in a table for each memoized
+    /// parser, so they are dropped when the parse ends, unless they are kept
+    /// in the [`Version`] of the parse for [`Parser::reparse`].
+    ///
+    /// This parser must not be left-recursive, but it may be part of a
+    /// [`left_recursive`](primitive::left_recursive) parser.
+    pub fn memoize(self) -> Parser<'a, I, O, E, St>
+    where
+        O: Clone + MaybeSync,
+        E: Clone + MaybeSync,
+    {
+        let grammar = self.grammar.clone();
+        let id = MemoId::<MemoEntry<O, E>>::new();
+
+        Parser::new(move |input, at, session| {
+            let key = session.memo_key(at);
+            let entry = match session.memo(&id).get(&key) {
+                Some(entry) => entry.clone(),
+                None => match reuse(session, &id, &key) {
+                    Some(entry) => {
+                        session.memo(&id).insert(key, entry.clone());
+                        entry
+                    }
+                    None => {
+                        let (seed_reads, offset_reads, behind_reads) = (
+                            session.seed_reads(),
+                            session.offset_reads(),
+                            session.behind_reads(),
+                        );
+                        let ((result, records), read) = session.reading(at, |session| {
+                            session.capture(|session| self.parse_with(input, at, session))
+                        });
+                        let entry = MemoEntry {
+                            result,
+                            records,
+                            read,
+                            offsets: session.offset_reads() != offset_reads,
+                            behind: session.behind_reads() != behind_reads,
+                        };
+
+                        // results built on a left-recursive seed may still grow
+                        if session.seed_reads() == seed_reads {
+                            session.memo(&id).insert(key, entry.clone());
+                        }
+                        entry
+                    }
+                },
+            };
+
+            session.read_to(entry.read);
+            if entry.offsets {
+                session.read_offset();
+            }
+            if entry.behind {
+                session.read_behind();
+            }
+            session.replay(entry.records);
+            entry.result
         })
+        .with_grammar(grammar)
     }
 
     /// Parse with `self`; on failure, parse with `other`.
     /// Fatal errors will short-circuit.
-    pub fn or(self, other: Parser<'a, I, O, E>) -> Parser<'a, I, O, E> {
-        Parser::new(move |input: I, at| match self.parse_at(input.clone(), at) {
-            Ok(ok) => Ok(ok),
-            Err(err) => match err.recover {
-                Recover::Recoverable => other.parse_at(input, at),
-                Recover::Fatal => Err(err),
-            },
+    pub fn or(self, other: Parser<'a, I, O, E, St>) -> Parser<'a, I, O, E, St> {
+        let grammar = Node::Choice(vec![self.node(), other.node()]);
+        Parser::new(move |input: I, at, session| {
+            let checkpoint = session.checkpoint();
+            match self.parse_with(input.clone(), at, session) {
+                Ok(ok) => Ok(ok),
+                Err(err) => match err.recover {
+                    Recover::Recoverable => {
+                        session.backtrack(checkpoint, err);
+                        other.parse_with(input, at, session)
+                    }
+                    Recover::Fatal | Recover::Incomplete { .. } => Err(err),
+                },
+            }
+        })
+        .with_grammar(Rc::new(grammar))
+    }
+
+    /// Like [`Parser::or`], but if both parsers fail recoverably, keep the
+    /// error that got furthest, merging the two if they are at the same
+    /// offset. See [`Merge`].
+    pub fn or_merge(self, other: Parser<'a, I, O, E, St>) -> Parser<'a, I, O, E, St>
+    where
+        E: Merge,
+    {
+        let grammar = Node::Choice(vec![self.node(), other.node()]);
+        Parser::new(move |input: I, at, session| {
+            let checkpoint = session.checkpoint();
+            match self.parse_with(input.clone(), at, session) {
+                Ok(ok) => Ok(ok),
+                Err(err) => match err.recover {
+                    Recover::Recoverable => {
+                        session.rewind(checkpoint);
+                        other
+                            .parse_with(input, at, session)
+                            .map_err(|e| match e.recover {
+                                Recover::Recoverable => err.merge(e),
+                                Recover::Fatal | Recover::Incomplete { .. } => e,
+                            })
+                    }
+                    Recover::Fatal | Recover::Incomplete { .. } => Err(err),
+                },
+            }
         })
+        .with_grammar(Rc::new(grammar))
     }
 
     /// Parse with `self`, then parse the remaining input with `other`,
     /// gathering both outputs into a tuple.
-    pub fn then<O1: 'a>(self, snd: Parser<'a, I, O1, E>) -> Parser<'a, I, (O, O1), E> {
-        Parser::new(move |input: I, at| {
-            let (o, rest) = self.parse_at(input.clone(), at)?;
-            let (o1, rest) = snd.parse_at(input, rest)?;
+    pub fn then<O1: 'a>(self, snd: Parser<'a, I, O1, E, St>) -> Parser<'a, I, (O, O1), E, St> {
+        let grammar = Node::Sequence(vec![self.node(), snd.node()]);
+        Parser::new(move |input: I, at, session| {
+            let (o, rest) = self.parse_with(input.clone(), at, session)?;
+            let (o1, rest) = snd.parse_with(input, rest, session)?;
             Ok(((o, o1), rest))
         })
+        .with_grammar(Rc::new(grammar))
     }
 
     /// Parse with `self`, then parse with `right`, ignoring its output and
     /// returning the output of self.
-    pub fn left<O1: 'a>(self, right: Parser<'a, I, O1, E>) -> Parser<'a, I, O, E> {
-        Parser::new(move |input: I, at| {
-            let (o, rest) = self.parse_at(input.clone(), at)?;
-            let (_, rest) = right.parse_at(input, rest)?;
+    pub fn left<O1: 'a>(self, right: Parser<'a, I, O1, E, St>) -> Parser<'a, I, O, E, St> {
+        let grammar = Node::Sequence(vec![self.node(), right.node()]);
+        Parser::new(move |input: I, at, session| {
+            let (o, rest) = self.parse_with(input.clone(), at, session)?;
+            let (_, rest) = right.parse_with(input, rest, session)?;
             Ok((o, rest))
         })
+        .with_grammar(Rc::new(grammar))
     }
 
     /// Parse with `self`, ignoring its output, then parse with `right`,
     /// returning its output.
-    pub fn right<O1: 'a>(self, right: Parser<'a, I, O1, E>) -> Parser<'a, I, O1, E> {
-        Parser::new(move |input: I, at| {
-            let (_, rest) = self.parse_at(input.clone(), at)?;
-            let (o, rest) = right.parse_at(input, rest)?;
+    pub fn right<O1: 'a>(self, right: Parser<'a, I, O1, E, St>) -> Parser<'a, I, O1, E, St> {
+        let grammar = Node::Sequence(vec![self.node(), right.node()]);
+        Parser::new(move |input: I, at, session| {
+            let (_, rest) = self.parse_with(input.clone(), at, session)?;
+            let (o, rest) = right.parse_with(input, rest, session)?;
             Ok((o, rest))
         })
+        .with_grammar(Rc::new(grammar))
     }
 
     /// Make this parser optional. Succeeds on recoverable errors.
-    pub fn optional(self) -> Parser<'a, I, Option<O>, E> {
-        Parser::new(move |input, at| match self.parse_at(input, at) {
-            Ok((o, rest)) => Ok((Some(o), rest)),
-            Err(err) => match err.recover {
-                Recover::Recoverable => Ok((None, at)),
-                Recover::Fatal => Err(err),
-            },
+    pub fn optional(self) -> Parser<'a, I, Option<O>, E, St> {
+        let grammar = Node::Repeat {
+            item: self.node(),
+            min: 0,
+            max: Some(1),
+        };
+        Parser::new(move |input, at, session| {
+            let checkpoint = session.checkpoint();
+            match self.parse_with(input, at, session) {
+                Ok((o, rest)) => Ok((Some(o), rest)),
+                Err(err) => match err.recover {
+                    Recover::Recoverable => {
+                        session.backtrack(checkpoint, err);
+                        Ok((None, at))
+                    }
+                    Recover::Fatal | Recover::Incomplete { .. } => Err(err),
+                },
+            }
         })
+        .with_grammar(Rc::new(grammar))
     }
 
     /// Surround a parser with delimiter parsers.
     pub fn surround<OLeft: 'a, ORight: 'a>(
         self,
-        left: Parser<'a, I, OLeft, E>,
-        right: Parser<'a, I, ORight, E>,
-    ) -> Parser<'a, I, O, E> {
+        left: Parser<'a, I, OLeft, E, St>,
+        right: Parser<'a, I, ORight, E, St>,
+    ) -> Parser<'a, I, O, E, St> {
         left.right(self).left(right)
     }
 
     /// Repeat this parser indefinitely until failure.
     /// This is equivalent to `.many_with(None, None)`.
-    pub fn many(self) -> Parser<'a, I, Vec<O>, E> {
+    pub fn many(self) -> Parser<'a, I, Vec<O>, E, St> {
         self.many_with(None, None).map_err(|e| e.unwrap())
     }
 
@@ -216,8 +593,13 @@ impl<'a, I: Clone + 'a, O: 'a, E: 'a> Parser<'a, I, O, E> {
         self,
         at_least: Option<usize>,
         at_most: Option<usize>,
-    ) -> Parser<'a, I, Vec<O>, Option<E>> {
-        Parser::new(move |input: I, at| {
+    ) -> Parser<'a, I, Vec<O>, Option<E>, St> {
+        let grammar = Node::Repeat {
+            item: self.node(),
+            min: at_least.unwrap_or(0),
+            max: at_most,
+        };
+        Parser::new(move |input: I, at, session| {
             let mut os = Vec::new();
             let mut rest = at;
             loop {
@@ -225,146 +607,381 @@ impl<'a, I: Clone + 'a, O: 'a, E: 'a> Parser<'a, I, O, E> {
                     break;
                 }
 
-                match self.parse_at(input.clone(), rest) {
+                let checkpoint = session.checkpoint();
+                match session.nest(Some, |session| {
+                    self.parse_with(input.clone(), rest, session)
+                }) {
                     Ok((o, r)) => {
                         os.push(o);
                         rest = r;
                     }
                     Err(err) => match err.recover {
-                        Recover::Recoverable => break,
-                        Recover::Fatal => return Err(err.map(Some)),
+                        Recover::Recoverable => {
+                            session.backtrack(checkpoint, err.map(Some));
+                            break;
+                        }
+                        Recover::Fatal | Recover::Incomplete { .. } => return Err(err.map(Some)),
                     },
                 }
             }
 
             if at_least.is_some_and(|min| os.len() < min) {
-                Err(Error {
-                    inner: None,
-                    recover: Recover::Recoverable,
-                    // TODO: at was mutated, is this correct?
-                    at,
-                })
+                // TODO: at was mutated, is this correct?
+                Err(Error::new(None, at))
             } else {
                 Ok((os, rest))
             }
         })
+        .with_grammar(Rc::new(grammar))
     }
 
     /// Parse zero or more `self`s, separated with `by`. This allows a trailing
     /// separator.
-    pub fn separate<O1: 'a>(self, by: Parser<'a, I, O1, E>) -> Parser<'a, I, Vec<O>, E> {
-        Parser::new(move |input: I, mut at| {
+    pub fn separate<O1: 'a>(self, by: Parser<'a, I, O1, E, St>) -> Parser<'a, I, Vec<O>, E, St> {
+        let grammar = Node::Separated {
+            item: self.node(),
+            separator: by.node(),
+        };
+        Parser::new(move |input: I, mut at, session| {
             let mut os = Vec::new();
             loop {
-                match self.parse_at(input.clone(), at) {
+                let checkpoint = session.checkpoint();
+                match self.parse_with(input.clone(), at, session) {
                     Ok((o, rest)) => {
                         os.push(o);
                         at = rest;
                     }
                     Err(err) => match err.recover {
-                        Recover::Recoverable => break,
-                        Recover::Fatal => return Err(err.fail()),
+                        Recover::Recoverable => {
+                            session.backtrack(checkpoint, err);
+                            break;
+                        }
+                        Recover::Fatal | Recover::Incomplete { .. } => return Err(err.fail()),
                     },
                 }
 
-                match by.parse_at(input.clone(), at) {
+                let checkpoint = session.checkpoint();
+                match by.parse_with(input.clone(), at, session) {
                     Ok((_, rest)) => {
                         at = rest;
                     }
                     Err(err) => match err.recover {
-                        Recover::Recoverable => break,
-                        Recover::Fatal => return Err(err.fail()),
+                        Recover::Recoverable => {
+                            session.backtrack(checkpoint, err);
+                            break;
+                        }
+                        Recover::Fatal | Recover::Incomplete { .. } => return Err(err.fail()),
                     },
                 }
             }
 
             Ok((os, at))
         })
+        .with_grammar(Rc::new(grammar))
     }
 
     /// Drop this parser's output.
-    pub fn ignore(self) -> Parser<'a, I, (), E> {
+    pub fn ignore(self) -> Parser<'a, I, (), E, St> {
         self.map(|_| ())
     }
 
     /// Drop this parser's error.
-    pub fn ignore_err(self) -> Parser<'a, I, O, ()> {
+    pub fn ignore_err(self) -> Parser<'a, I, O, (), St> {
         self.map_err(|_| ())
     }
 
     /// Associate the output with the range of indices that the parser consumed.
-    pub fn with_span(self) -> Parser<'a, I, (O, Range<usize>), E> {
-        Parser::new(move |input, at| {
-            let (o, rest) = self.parse_at(input, at)?;
+    pub fn with_span(self) -> Parser<'a, I, (O, Range<usize>), E, St> {
+        let grammar = self.grammar.clone();
+        Parser::new(move |input, at, session| {
+            let (o, rest) = self.parse_with(input, at, session)?;
+            session.read_offset();
             Ok(((o, at..rest), rest))
         })
+        .with_grammar(grammar)
     }
-}
 
-/// Implementations on parsers that accept slices as input.
-impl<'a, S: Slice<'a> + ?Sized, O: 'a, E: 'a> Parser<'a, &'a S, O, E> {
-    pub fn input(self) -> Parser<'a, &'a S, &'a S, E> {
-        Parser::new(move |input, at| {
-            let (_, rest) = self.parse_at(input, at)?;
-            Ok((input.index_between(at, rest), rest))
+    /// Like [`Parser::with_span`], but output a [`Spanned`].
+    pub fn spanned(self) -> Parser<'a, I, Spanned<O>, E, St> {
+        self.with_span().map(|(o, span)| Spanned::new(o, span))
+    }
+
+    /// Mark the parser as a rule named `label`. When it fails, the error is
+    /// given the label and the span from where the rule started, unless it
+    /// already belongs to a rule nested in this one that consumed some input.
+    /// See [`Error::span`].
+    ///
+    /// If the parser fails without consuming any input, what it expected is
+    /// best described by the label, which replaces the inner error, and the
+    /// errors recorded there. See [`Label`].
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use hair::primitive::just;
+    ///
+    /// let pair = just("(").then(just("a")).then(just(")")).labelled("pair");
+    /// let err = pair.parse_at("(a]", 0).unwrap_err();
+    /// assert_eq!((err.label(), err.span()), (Some("pair"), 0..2));
+    /// ```
+    pub fn labelled(self, label: &'static str) -> Parser<'a, I, O, E, St>
+    where
+        E: Label,
+    {
+        let grammar = Node::Rule(label, self.node());
+        Parser::new(move |input, at, session| {
+            let (result, records) = session.capture(|session| self.parse_with(input, at, session));
+            match result {
+                Err(err) if err.at == at => {
+                    session.replay(records.beyond(at));
+                    Err(Error {
+                        inner: E::label(label),
+                        start: at,
+                        label: Some(label),
+                        ..err
+                    })
+                }
+                Err(err) if err.label.is_none() || err.at == err.start => {
+                    session.replay(records);
+                    Err(Error {
+                        start: at,
+                        label: Some(label),
+                        ..err
+                    })
+                }
+                result => {
+                    session.replay(records);
+                    result
+                }
+            }
         })
+        .with_grammar(Rc::new(grammar))
     }
-}
 
-/// This type wraps errors as they propagate upward through parsers. `E` is the
-/// parser's actual error type, whether it be `()` or a user-defined error.
-///
-/// hair borrows the error propagation mechanism seen in some other combinator
-/// libraries, such as nom. Errors have a state of 'recoverable' or 'fatal',
-/// where fatal errors will always propagate upward regardless of alternatives.
-/// hair's primitive combinators will never yield a fatal error - it is
-/// up to the user to decide which parsers should throw fatal errors with
-/// [`Parser::expect`].
-///
-/// Introducing fatal-throwing parsers will never cause another failing parser
-/// to succeed. It is only for providing more accurate error messages.
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub struct Error<E> {
-    inner: E,
-    recover: Recover,
-    at: usize,
+    /// Record each run of this parser as an [`Attempt`](trace::Attempt) of
+    /// the rule named `rule`, when parsing with [`Parser::parse_traced`].
+    /// Parses that aren't traced only pay for checking that they aren't.
+    ///
+    /// Results reused by [`Parser::memoize`] aren't recorded again.
+    pub fn trace(self, rule: &'static str) -> Parser<'a, I, O, E, St> {
+        let grammar = self.grammar.clone();
+        Parser::new(move |input, at, session| {
+            let Some(tracer) = session.tracer() else {
+                return self.parse_with(input, at, session);
+            };
+            tracer.enter(rule, at);
+
+            let result = self.parse_with(input, at, session);
+            let outcome = match &result {
+                Ok((_, end)) => Outcome::Ok { end: *end },
+                Err(err) => Outcome::Err {
+                    at: err.at,
+                    recover: err.recover,
+                },
+            };
+            session.tracer().unwrap().exit(outcome);
+            result
+        })
+        .with_grammar(grammar)
+    }
+
+    /// Add a frame for the construct named `label` to the
+    /// [context](Error::context) of errors passing through this parser, so
+    /// a report can tell what was being parsed where a deep error occurred.
+    /// [`Report::from_error`](report::Report::from_error) shows the frames as
+    /// notes.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use hair::primitive::just;
+    ///
+    /// let member = just("a").then(just(":")).then(just("1").expect()).context("member");
+    /// let object = just("{").then(member).then(just("}")).context("object");
+    ///
+    /// let err = object.parse_at("{a:2}", 0).unwrap_err();
+    /// let frames: Vec<_> = err.context().iter().map(|c| (c.label, c.span.clone())).collect();
+    /// assert_eq!(frames, vec![("member", 1..3), ("object", 0..3)]);
+    /// ```
+    pub fn context(self, label: &'static str) -> Parser<'a, I, O, E, St> {
+        let grammar = self.grammar.clone();
+        Parser::new(move |input, at, session| {
+            self.parse_with(input, at, session).map_err(|mut err| {
+                err.context.push(Context {
+                    label,
+                    span: at..err.at,
+                });
+                err
+            })
+        })
+        .with_grammar(grammar)
+    }
+
+    /// Like [`Parser::with_span`], but resolve the range into [`Location`]s
+    /// using `index`, which should be built from the same input.
+    pub fn with_location(
+        self,
+        index: &'a LineIndex<'a>,
+    ) -> Parser<'a, I, (O, Range<Location>), E, St> {
+        self.with_span()
+            .map(|(o, span)| (o, index.location(span.start)..index.location(span.end)))
+    }
 }
 
-impl<E> Error<E> {
-    pub fn new(inner: E, at: usize) -> Self {
-        Self {
-            inner,
-            recover: Recover::Recoverable,
-            at,
-        }
+/// The entry points of parsers that don't use the user state, see
+/// [`Parser::parse_with_state`] and the other `_with_state` entry points for
+/// those that do.
+impl<'a, I: Clone + 'a, O: 'a, E: 'a> Parser<'a, I, O, E> {
+    /// Parse starting at an offset, in a new session. On failure, this
+    /// reports the error that got furthest into the input, like
+    /// [`Parser::parse`].
+    ///
+    /// Since the session is new, nothing in the session of an enclosing
+    /// parse is seen or kept, such as the errors it recorded: errors that got
+    /// further than the enclosing parser's own are lost. Parsers calling
+    /// other parsers should use [`Parser::parse_with`] instead.
+    pub fn parse_at(&self, i: I, n: usize) -> ParseResult<O, E> {
+        let mut session = Session::new();
+        let result = self.parse_with(i, n, &mut session);
+        session.finish_furthest(result)
     }
 
-    /// Map the error's inner value.
-    pub fn map<F, E1>(self, f: F) -> Error<E1>
-    where
-        F: Fn(E) -> E1,
-    {
-        let Error { inner, recover, at } = self;
-        Error {
-            inner: f(inner),
-            recover,
-            at,
-        }
+    /// Parse from the beginning, and collect the output.
+    ///
+    /// Recoverable errors that combinators backtracked over are remembered,
+    /// so on failure, this reports the error that got furthest into the
+    /// input rather than the one at the last point of backtracking. See
+    /// [`Session`].
+    pub fn parse(&self, i: I) -> Result<O, (E, usize)> {
+        output(self.parse_at(i, 0))
     }
 
-    /// Make this error fatal.
-    #[must_use]
-    pub fn fail(self) -> Error<E> {
-        Error {
-            recover: Recover::Fatal,
-            ..self
-        }
+    /// Like [`Parser::parse`], but on failure, collect every error recorded
+    /// at the furthest offset reached, e.g. one for each alternative that
+    /// failed there. The error the parser itself returned comes last, if it
+    /// is among them.
+    pub fn parse_furthest(&self, i: I) -> Result<O, (Vec<E>, usize)> {
+        let mut session = Session::new();
+        let result = self.parse_with(i, 0, &mut session);
+        session.finish(result).map(|(o, _)| o).map_err(|errors| {
+            let at = errors[0].at;
+            (errors.into_iter().map(|err| err.inner).collect(), at)
+        })
+    }
+
+    /// Parse from the beginning, continuing past errors that parsers recover
+    /// from with [`Parser::recover_with`]. Returns the output, if parsing
+    /// succeeded despite them, and every error in the order they occurred; if
+    /// parsing failed, the furthest error comes last.
+    pub fn parse_recovering(&self, i: I) -> (Option<O>, Vec<Error<E>>) {
+        let (o, errors, ()) = self.parse_recovering_with_state(i, ());
+        (o, errors)
+    }
+
+    /// Like [`Parser::parse`], but also record what the parsers marked with
+    /// [`Parser::trace`] attempted. See [`trace`].
+    pub fn parse_traced(&self, i: I) -> (Result<O, (E, usize)>, Trace) {
+        let (result, trace, ()) = self.parse_traced_with_state(i, ());
+        (result, trace)
+    }
+
+    /// Like [`Parser::parse`], but also return the [`Version`] of the parse,
+    /// for [`Parser::reparse`]. The results of memoized parsers are kept in
+    /// it until it's dropped.
+    pub fn parse_versioned(&self, i: I) -> (Result<O, (E, usize)>, Version<'a>) {
+        let (result, version, ()) = self.parse_versioned_with_state(i, ());
+        (result, version)
+    }
+
+    /// Parse `i`, which is the input of the `previous` parse by this parser
+    /// with `edit` made to it, reusing the memoized results of that parse
+    /// that the edit doesn't affect. See [`incremental`].
+    pub fn reparse(
+        &self,
+        i: I,
+        previous: Version<'a>,
+        edit: impl Into<Edit>,
+    ) -> (Result<O, (E, usize)>, Version<'a>) {
+        let (result, version, ()) = self.reparse_with_state(i, previous, edit, ());
+        (result, version)
+    }
+
+    /// Run this parser, which doesn't use the user state, in a parse with
+    /// state of type `St`, to combine it with parsers that do. See
+    /// [`Session::state`].
+    pub fn stateful<St: 'a>(self) -> Parser<'a, I, O, E, St> {
+        let grammar = self.grammar.clone();
+        Parser::new(move |input, at, session: &mut Session<'a, E, St>| {
+            session.stateless(|session| self.parse_with(input, at, session))
+        })
+        .with_grammar(grammar)
+    }
+}
+
+/// Implementations on parsers that accept slices as input.
+impl<'a, S: Slice<'a> + ?Sized, O: 'a, E: 'a, St: 'a> Parser<'a, &'a S, O, E, St> {
+    pub fn input(self) -> Parser<'a, &'a S, &'a S, E, St> {
+        let grammar = self.grammar.clone();
+        Parser::new(move |input, at, session| {
+            let (_, rest) = self.parse_with(input, at, session)?;
+            Ok((input.index_between(at, rest), rest))
+        })
+        .with_grammar(grammar)
     }
 }
 
-/// State within [`Error`]. Errors with `Recover::Fatal` short-circuit.
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub enum Recover {
-    Recoverable,
-    Fatal,
+/// The result of an entry point like [`Parser::parse`]: the output, or the
+/// error and where it occurred.
+fn output<O, E>(result: ParseResult<O, E>) -> Result<O, (E, usize)> {
+    result
+        .map(|(o, _)| o)
+        .map_err(|Error { inner, at, .. }| (inner, at))
+}
+
+/// A result of a memoized parser, with what it recorded.
+#[derive(Clone)]
+struct MemoEntry<O, E> {
+    result: ParseResult<O, E>,
+    records: Records<E>,
+    /// How far the parser read, see [`Session::read_to`].
+    read: usize,
+    /// Whether the result holds offsets, see [`Session::read_offset`].
+    offsets: bool,
+    /// Whether the parser read the input before it, see
+    /// [`Session::read_behind`].
+    behind: bool,
+}
+
+/// The result of the memoized parser `id` at `key` in the parse that
+/// [`Parser::reparse`] reuses, if the edit since doesn't affect it, moved to
+/// where it is in the edited input. Results that hold offsets are only
+/// reused where they didn't move, and those that read the input before them
+/// only before the edit.
+fn reuse<'a, O, E, St>(
+    session: &Session<'a, E, St>,
+    id: &MemoId<MemoEntry<O, E>>,
+    key: &MemoKey,
+) -> Option<MemoEntry<O, E>>
+where
+    O: Clone + MaybeSync + 'a,
+    E: Clone + MaybeSync + 'a,
+{
+    let reuse = session.reuse()?;
+    let (previous, delta) = reuse.previous_key(key)?;
+    let entry = reuse.memo.get(id)?.get(&previous)?;
+    if !reuse.unaffected(previous.at, entry.read, entry.behind) || (entry.offsets && delta != 0) {
+        return None;
+    }
+
+    let result = match &entry.result {
+        Ok((o, end)) => Ok((o.clone(), end.wrapping_add_signed(delta))),
+        Err(err) => Err(err.clone().shift(delta)),
+    };
+    Some(MemoEntry {
+        result,
+        records: entry.records.clone().shift(delta),
+        read: entry.read.wrapping_add_signed(delta),
+        offsets: entry.offsets,
+        behind: entry.behind,
+    })
 }
@@ -0,0 +1,144 @@
+//! Conversion of input offsets into human-readable positions. See [`LineIndex`].
+//!
+//! ## Example
+//!
+//! ```rust
+//! use hair::{location::LineIndex, primitive::just};
+//!
+//! let source = "abc\nhello";
+//! let index = LineIndex::new(source);
+//! let p = just("abc\n").right(just("hello").with_location(&index));
+//!
+//! let (_, span) = p.parse(source).unwrap();
+//! assert_eq!((span.start.line, span.start.column), (2, 1));
+//! assert_eq!((span.end.line, span.end.column), (2, 6));
+//! ```
+
+use std::fmt;
+
+/// A position in a `&str` input. Lines and columns are 1-based, so that they
+/// can be shown to users as-is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Location {
+    /// The byte offset into the input, as returned by parsers.
+    pub offset: usize,
+    pub line: usize,
+    /// The column, counted in bytes.
+    pub column: usize,
+    /// The column, counted in `char`s.
+    pub char_column: usize,
+}
+
+impl fmt::Display for Location {
+    /// Formats as `line:column`, using the `char`-based column.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.char_column)
+    }
+}
+
+/// An index of the line starts in some source text, for converting the
+/// `usize` offsets used by parsers and [`Error`](crate::Error)s into
+/// [`Location`]s.
+///
+/// Building the index is linear in the length of the source; lookups are
+/// logarithmic in the number of lines.
+#[derive(Clone, Debug)]
+pub struct LineIndex<'s> {
+    source: &'s str,
+    /// Byte offsets of the first character of each line.
+    starts: Vec<usize>,
+}
+
+impl<'s> LineIndex<'s> {
+    pub fn new(source: &'s str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+
+        Self { source, starts }
+    }
+
+    /// The source text this index was built from.
+    pub fn source(&self) -> &'s str {
+        self.source
+    }
+
+    /// The number of lines in the source. An empty source, or one ending in a
+    /// newline, has an empty last line.
+    pub fn line_count(&self) -> usize {
+        self.starts.len()
+    }
+
+    /// The 1-based line containing `offset`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset` is past the end of the source.
+    pub fn line(&self, offset: usize) -> usize {
+        assert!(
+            offset <= self.source.len(),
+            "offset {offset} is out of bounds for a source of length {}",
+            self.source.len()
+        );
+
+        self.starts.partition_point(|&start| start <= offset)
+    }
+
+    /// Locate `offset` in the source. Offsets that fall inside a multi-byte
+    /// `char` are counted as part of that `char`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset` is past the end of the source.
+    pub fn location(&self, offset: usize) -> Location {
+        let line = self.line(offset);
+        let start = self.starts[line - 1];
+        let char_column = self.source[start..]
+            .char_indices()
+            .take_while(|&(i, c)| start + i + c.len_utf8() <= offset)
+            .count();
+
+        Location {
+            offset,
+            line,
+            column: offset - start + 1,
+            char_column: char_column + 1,
+        }
+    }
+
+    /// The byte range of a 1-based line, excluding its line terminator
+    /// (`\n` or `\r\n`).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `line` is zero or greater than [`LineIndex::line_count`].
+    pub fn line_range(&self, line: usize) -> std::ops::Range<usize> {
+        assert!(
+            (1..=self.line_count()).contains(&line),
+            "line {line} is out of bounds for a source with {} lines",
+            self.line_count()
+        );
+
+        let start = self.starts[line - 1];
+        let end = self
+            .starts
+            .get(line)
+            .map_or(self.source.len(), |&next| next - 1);
+        let end = if self.source[start..end].ends_with('\r') {
+            end - 1
+        } else {
+            end
+        };
+
+        start..end
+    }
+
+    /// The text of a 1-based line, excluding its line terminator.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `line` is zero or greater than [`LineIndex::line_count`].
+    pub fn line_text(&self, line: usize) -> &'s str {
+        &self.source[self.line_range(line)]
+    }
+}
@@ -0,0 +1,323 @@
+//! Expression parsing with operator precedence (Pratt parsing). See [`pratt`].
+//!
+//! ## Example
+//!
+//! ```rust
+//! use hair::{
+//!     pratt::{infix, pratt, prefix, ternary, Associativity::*},
+//!     primitive::{just, unit},
+//! };
+//!
+//! let op = |c| unit::<str>().filter(move |&d| c == d);
+//! let atom = unit::<str>().filter_map(|c| c.to_digit(10).map(|d| d as i32));
+//! let expr = pratt(
+//!     atom,
+//!     [
+//!         ternary(just("?"), just(":"), 1, |c, _, a, _, b| if c != 0 { a } else { b }),
+//!         infix(op('+'), 2, Left, |a, _, b| a + b),
+//!         infix(op('-'), 2, Left, |a, _, b| a - b),
+//!         infix(op('*'), 3, Left, |a, _, b| a * b),
+//!         infix(op('^'), 4, Right, |a: i32, _, b| a.pow(b as u32)),
+//!         prefix(op('-'), 5, |_, a: i32| -a),
+//!     ],
+//! );
+//!
+//! assert_eq!(expr.parse("1+2*3"), Ok(7));
+//! assert_eq!(expr.parse("9-3-2"), Ok(4));
+//! assert_eq!(expr.parse("2^3^2"), Ok(512));
+//! assert_eq!(expr.parse("-2*3"), Ok(-6));
+//! assert_eq!(expr.parse("0?1:1-1?2:3"), Ok(3));
+//! ```
+
+use crate::{sync::Rc, MaybeSync, ParseResult, Parser, Recover, Session};
+
+/// How operators of the same precedence group, e.g. `a - b - c` is
+/// `(a - b) - c` since `-` is left-associative.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Associativity {
+    Left,
+    Right,
+}
+
+/// An operator in the table given to [`pratt`], built with [`prefix`],
+/// [`infix`], [`postfix`] or [`ternary`].
+pub struct Operator<'a, I, O, E, St = ()> {
+    /// Parses the operator, outputting how to fold it into an expression.
+    parser: Parser<'a, I, Fold<'a, I, O, E, St>, E, St>,
+    prefix: bool,
+    /// How tightly the operator binds to the operand on its left and right.
+    /// Higher is tighter; 0 is looser than any operator.
+    left: u64,
+    right: u64,
+}
+
+enum Fold<'a, I, O, E, St> {
+    Unary(Box<dyn FnOnce(O) -> O + 'a>),
+    Binary(Box<dyn FnOnce(O, O) -> O + 'a>),
+    /// Parses the second part of the operator, outputting the fold.
+    Ternary(Box<Close<'a, I, O, E, St>>),
+}
+
+type Close<'a, I, O, E, St> =
+    dyn FnOnce(I, usize, &mut Session<'a, E, St>) -> ParseResult<Fold3<'a, O>, E> + 'a;
+type Fold3<'a, O> = Box<dyn FnOnce(O, O, O) -> O + 'a>;
+
+/// The binding powers of an operator on its left and right. The operand on
+/// the side it doesn't associate to must bind tighter, so that e.g. in
+/// `a - b - c`, the second `-` can't take `b` from the first.
+fn binding(precedence: u32, associativity: Associativity) -> (u64, u64) {
+    let power = 2 * u64::from(precedence) + 1;
+    match associativity {
+        Associativity::Left => (power, power + 1),
+        Associativity::Right => (power + 1, power),
+    }
+}
+
+/// An operator that comes before its operand, such as `-a`. Operators with a
+/// higher `precedence` bind tighter.
+pub fn prefix<'a, I, O, E, St, P, F>(
+    operator: Parser<'a, I, P, E, St>,
+    precedence: u32,
+    fold: F,
+) -> Operator<'a, I, O, E, St>
+where
+    I: Clone + 'a,
+    O: 'a,
+    E: 'a,
+    St: 'a,
+    P: 'a,
+    F: Fn(P, O) -> O + MaybeSync + 'a,
+{
+    let fold = Rc::new(fold);
+    let (_, right) = binding(precedence, Associativity::Right);
+    Operator {
+        parser: operator.map(move |p| {
+            let fold = Rc::clone(&fold);
+            Fold::Unary(Box::new(move |o| fold(p, o)))
+        }),
+        prefix: true,
+        left: 0,
+        right,
+    }
+}
+
+/// An operator between two operands, such as `a + b`. Operators with a
+/// higher `precedence` bind tighter.
+pub fn infix<'a, I, O, E, St, P, F>(
+    operator: Parser<'a, I, P, E, St>,
+    precedence: u32,
+    associativity: Associativity,
+    fold: F,
+) -> Operator<'a, I, O, E, St>
+where
+    I: Clone + 'a,
+    O: 'a,
+    E: 'a,
+    St: 'a,
+    P: 'a,
+    F: Fn(O, P, O) -> O + MaybeSync + 'a,
+{
+    let fold = Rc::new(fold);
+    let (left, right) = binding(precedence, associativity);
+    Operator {
+        parser: operator.map(move |p| {
+            let fold = Rc::clone(&fold);
+            Fold::Binary(Box::new(move |a, b| fold(a, p, b)))
+        }),
+        prefix: false,
+        left,
+        right,
+    }
+}
+
+/// An operator that comes after its operand, such as `a?`. Operators with a
+/// higher `precedence` bind tighter; at the same precedence, a postfix
+/// operator binds tighter than a prefix one.
+///
+/// The operator may be a whole construct, e.g. the argument list of a call
+/// `f(a, b)` or an index `a[i]`, with its output passed to `fold`. Since those
+/// contain expressions, the operator is usually built from the expression
+/// parser with [`recursive`](crate::primitive::recursive).
+pub fn postfix<'a, I, O, E, St, P, F>(
+    operator: Parser<'a, I, P, E, St>,
+    precedence: u32,
+    fold: F,
+) -> Operator<'a, I, O, E, St>
+where
+    I: Clone + 'a,
+    O: 'a,
+    E: 'a,
+    St: 'a,
+    P: 'a,
+    F: Fn(O, P) -> O + MaybeSync + 'a,
+{
+    let fold = Rc::new(fold);
+    let (left, _) = binding(precedence, Associativity::Left);
+    Operator {
+        parser: operator.map(move |p| {
+            let fold = Rc::clone(&fold);
+            Fold::Unary(Box::new(move |o| fold(o, p)))
+        }),
+        prefix: false,
+        left,
+        right: 0,
+    }
+}
+
+/// A right-associative operator in two parts between three operands, such as
+/// `a ? b : c`. The middle operand may be any expression, as if it were
+/// parenthesized. Operators with a higher `precedence` bind tighter.
+pub fn ternary<'a, I, O, E, St, P, Q, F>(
+    open: Parser<'a, I, P, E, St>,
+    close: Parser<'a, I, Q, E, St>,
+    precedence: u32,
+    fold: F,
+) -> Operator<'a, I, O, E, St>
+where
+    I: Clone + 'a,
+    O: 'a,
+    E: 'a,
+    St: 'a,
+    P: 'a,
+    Q: 'a,
+    F: Fn(O, P, O, Q, O) -> O + MaybeSync + 'a,
+{
+    let fold = Rc::new(fold);
+    let (left, right) = binding(precedence, Associativity::Right);
+    Operator {
+        parser: open.map(move |p| {
+            let (close, fold) = (close.clone(), Rc::clone(&fold));
+            Fold::Ternary(Box::new(move |input, at, session| {
+                let (q, rest) = close.parse_with(input, at, session)?;
+                let fold: Fold3<'a, O> = Box::new(move |a, b, c| fold(a, p, b, q, c));
+                Ok((fold, rest))
+            }))
+        }),
+        prefix: false,
+        left,
+        right,
+    }
+}
+
+/// Parse expressions made of operands parsed by `atom` and the `operators`,
+/// which bind according to their precedence and associativity.
+///
+/// Operators are tried in the order they are given, and the first that
+/// parses is used, so an operator that is a prefix of another, like `-` of
+/// `->`, must come after it. A prefix operator may share its token with an
+/// infix or postfix operator, e.g. `-` for negation and subtraction.
+///
+/// Parentheses are up to `atom`, which can be given the expression parser
+/// with [`recursive`](crate::primitive::recursive).
+pub fn pratt<'a, I, O, E, St, Ops>(
+    atom: Parser<'a, I, O, E, St>,
+    operators: Ops,
+) -> Parser<'a, I, O, E, St>
+where
+    I: Clone + 'a,
+    O: 'a,
+    E: 'a,
+    St: 'a,
+    Ops: AsRef<[Operator<'a, I, O, E, St>]> + MaybeSync + 'a,
+{
+    Parser::new(move |input, at, session| climb(&atom, operators.as_ref(), 0, input, at, session))
+}
+
+/// Parse an expression, stopping at an operator that binds weaker than `min`
+/// on the left.
+fn climb<'a, I: Clone + 'a, O: 'a, E: 'a, St: 'a>(
+    atom: &Parser<'a, I, O, E, St>,
+    operators: &[Operator<'a, I, O, E, St>],
+    min: u64,
+    input: I,
+    at: usize,
+    session: &mut Session<'a, E, St>,
+) -> ParseResult<O, E> {
+    let (mut lhs, mut at) = match next(operators, true, input.clone(), at, session)? {
+        Some((operator, Fold::Unary(fold), rest)) => {
+            let (rhs, rest) = climb(
+                atom,
+                operators,
+                operator.right,
+                input.clone(),
+                rest,
+                session,
+            )?;
+            (fold(rhs), rest)
+        }
+        Some(_) => unreachable!("prefix operators are unary"),
+        None => atom.parse_with(input.clone(), at, session)?,
+    };
+
+    loop {
+        let checkpoint = session.checkpoint();
+        let Some((operator, fold, rest)) = next(operators, false, input.clone(), at, session)?
+        else {
+            break;
+        };
+
+        // the operator belongs to an enclosing expression
+        if operator.left < min {
+            session.rewind(checkpoint);
+            break;
+        }
+
+        (lhs, at) = match fold {
+            Fold::Unary(fold) => (fold(lhs), rest),
+            Fold::Binary(fold) => {
+                let (rhs, rest) = climb(
+                    atom,
+                    operators,
+                    operator.right,
+                    input.clone(),
+                    rest,
+                    session,
+                )?;
+                (fold(lhs, rhs), rest)
+            }
+            Fold::Ternary(close) => {
+                let (middle, rest) = climb(atom, operators, 0, input.clone(), rest, session)?;
+                let (fold, rest) = close(input.clone(), rest, session)?;
+                let (rhs, rest) = climb(
+                    atom,
+                    operators,
+                    operator.right,
+                    input.clone(),
+                    rest,
+                    session,
+                )?;
+                (fold(lhs, middle, rhs), rest)
+            }
+        };
+    }
+
+    Ok((lhs, at))
+}
+
+/// Parse the first of the prefix, or the other, `operators` that matches at
+/// `at`. Operators that don't match are backtracked over.
+#[allow(clippy::type_complexity)]
+fn next<'a, 'o, I: Clone + 'a, O: 'a, E: 'a, St: 'a>(
+    operators: &'o [Operator<'a, I, O, E, St>],
+    prefix: bool,
+    input: I,
+    at: usize,
+    session: &mut Session<'a, E, St>,
+) -> Result<Option<(&'o Operator<'a, I, O, E, St>, Fold<'a, I, O, E, St>, usize)>, crate::Error<E>>
+{
+    for operator in operators
+        .iter()
+        .filter(|operator| operator.prefix == prefix)
+    {
+        let checkpoint = session.checkpoint();
+        match operator.parser.parse_with(input.clone(), at, session) {
+            Ok((fold, rest)) => return Ok(Some((operator, fold, rest))),
+            Err(err) => match err.recover {
+                Recover::Recoverable => session.backtrack(checkpoint, err),
+                Recover::Fatal | Recover::Incomplete { .. } => return Err(err),
+            },
+        }
+    }
+
+    Ok(None)
+}
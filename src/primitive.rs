@@ -9,69 +9,314 @@
 //! assert_eq!(character('a').then(just("bc")).parse("abc"), Ok(('a', "bc")));
 //! ```
 
-use crate::{Error, Parser, Recover, Slice};
+use crate::{
+    error::Merge,
+    ext::{self, ParserExt},
+    grammar::Node,
+    session::{MemoId, Records},
+    sync::{OnceCell, Rc, Weak},
+    Error, MaybeSync, ParseResult, Parser, Recover, Session, Slice,
+};
 
 /// Successfully parse nothing.
 pub fn identity<'a, I: Clone + 'a>() -> Parser<'a, I, (), ()> {
-    Parser::new(|_, at| Ok(((), at)))
+    Parser::new(|_, at, _| Ok(((), at))).with_grammar(Rc::new(Node::Sequence(Vec::new())))
 }
 
 /// Parse and consume a single unit of the input.
 /// For `&[T]`, this is `&T`; for `&str`, this is `char`.
 pub fn unit<'a, S: Slice<'a> + ?Sized>() -> Parser<'a, &'a S, S::Item, ()> {
-    Parser::new(|input: &S, at| {
-        let rest = input.index_from(at);
-        if let Some((c, len)) = rest.first() {
-            Ok((c, at + len))
-        } else {
-            Err(Error::new((), at))
-        }
-    })
+    ext::unit().boxed()
 }
 
 /// If the remaining input starts with `expected`, output the match.
 pub fn just<'a, 'b: 'a, S>(expected: &'b S) -> Parser<'a, &'a S, &'a S, ()>
 where
-    S: Slice<'a> + PartialEq<S> + ?Sized,
+    S: Slice<'a> + PartialEq<S> + MaybeSync + ?Sized,
 {
-    Parser::new(move |input: &S, at| {
-        if input.index_from(at).len() >= expected.len()
-            && input.index_between(at, at + expected.len()) == expected
-        {
-            Ok((expected, at + expected.len()))
-        } else {
-            Err(Error::new((), at))
-        }
-    })
+    ext::just(expected)
+        .boxed()
+        .with_grammar(Rc::new(Node::Literal(Rc::new(move || expected))))
 }
 
 pub fn end<'a, S: Slice<'a> + ?Sized>() -> Parser<'a, &'a S, (), ()> {
-    Parser::new(move |input: &S, at| {
-        if input.len() == at {
+    Parser::new(move |input: &S, at, session| {
+        session.read_to(at + 1);
+        if input.len() == at && session.is_partial() {
+            Err(session.out_of_input((), at, 1))
+        } else if input.len() == at {
             Ok(((), at))
         } else {
             Err(Error::new((), at))
         }
     })
+    // the end of the input is nothing to generate, see `grammar`
+    .with_grammar(Rc::new(Node::Sequence(Vec::new())))
+}
+
+/// Output a copy of the user state of the parse, see [`Session::state`].
+/// The type of the state comes first, as in `get_state::<Symbols, _, _>()`.
+pub fn get_state<'a, St: Clone + 'a, I: Clone + 'a, E: 'a>() -> Parser<'a, I, St, E, St> {
+    Parser::new(|_, at, session: &mut Session<'a, E, St>| Ok((session.state().clone(), at)))
 }
 
 /// Try all parsers in sequence. Equivalent to `a.or(b).or(c)...`.
-pub fn any<'a, I: Clone + 'a, O: 'a, E: 'a, Ps>(parsers: Ps) -> Parser<'a, I, O, E>
+pub fn any<'a, I: Clone + 'a, O: 'a, E: 'a, St: 'a, Ps>(parsers: Ps) -> Parser<'a, I, O, E, St>
 where
-    Ps: AsRef<[Parser<'a, I, O, E>]> + 'a,
+    Ps: AsRef<[Parser<'a, I, O, E, St>]> + MaybeSync + 'a,
 {
-    Parser::new(move |input: I, at| {
+    let grammar = choice(parsers.as_ref());
+    Parser::new(move |input: I, at, session: &mut Session<'a, E, St>| {
+        let checkpoint = session.checkpoint();
         let mut last_error = None;
         for parser in parsers.as_ref() {
-            match parser.parse_at(input.clone(), at) {
+            match parser.parse_with(input.clone(), at, session) {
                 Ok((o, rest)) => return Ok((o, rest)),
                 Err(err) => match err.recover {
-                    Recover::Recoverable => last_error = Some(err),
-                    Recover::Fatal => return Err(err),
+                    Recover::Recoverable => {
+                        session.rewind(checkpoint);
+                        if let Some(last_error) = last_error.replace(err) {
+                            session.record(last_error);
+                        }
+                    }
+                    Recover::Fatal | Recover::Incomplete { .. } => return Err(err),
                 },
             }
         }
 
         Err(last_error.unwrap())
     })
+    .with_grammar(grammar)
+}
+
+/// Try all parsers in sequence like [`any`], but if all of them fail, merge
+/// their errors rather than returning only the last one. Equivalent to
+/// `a.or_merge(b).or_merge(c)...`. See [`Merge`].
+///
+/// # Panics
+///
+/// Panics when run if `parsers` is empty.
+pub fn any_merge<'a, I, O, E, St, Ps>(parsers: Ps) -> Parser<'a, I, O, E, St>
+where
+    I: Clone + 'a,
+    O: 'a,
+    E: Merge + 'a,
+    St: 'a,
+    Ps: AsRef<[Parser<'a, I, O, E, St>]> + MaybeSync + 'a,
+{
+    let grammar = choice(parsers.as_ref());
+    Parser::new(move |input: I, at, session: &mut Session<'a, E, St>| {
+        let checkpoint = session.checkpoint();
+        let mut error: Option<Error<E>> = None;
+        for parser in parsers.as_ref() {
+            match parser.parse_with(input.clone(), at, session) {
+                Ok((o, rest)) => return Ok((o, rest)),
+                Err(err) => match err.recover {
+                    Recover::Recoverable => {
+                        session.rewind(checkpoint);
+                        error = Some(match error {
+                            Some(error) => error.merge(err),
+                            None => err,
+                        });
+                    }
+                    Recover::Fatal | Recover::Incomplete { .. } => return Err(err),
+                },
+            }
+        }
+
+        Err(error.unwrap())
+    })
+    .with_grammar(grammar)
+}
+
+fn choice<'a, I: Clone + 'a, O: 'a, E: 'a, St: 'a>(
+    parsers: &[Parser<'a, I, O, E, St>],
+) -> Rc<Node<'a, I>> {
+    Rc::new(Node::Choice(
+        parsers.iter().map(|parser| parser.node()).collect(),
+    ))
+}
+
+/// Build a recursive parser. `f` is given a parser that stands for the one
+/// being built, which it may use anywhere in the grammar it returns, except
+/// at its very start: a left-recursive parser never returns. See
+/// [`left_recursive`] for those.
+///
+/// # Example
+///
+/// ```rust
+/// use hair::primitive::{just, recursive};
+///
+/// // list := '(' list* ')'
+/// let list = recursive(|list| {
+///     list.many()
+///         .surround(just("("), just(")"))
+///         .map(|lists| lists.into_iter().sum::<usize>() + 1)
+/// });
+///
+/// assert_eq!(list.parse("(()(()))"), Ok(4));
+/// ```
+pub fn recursive<'a, I, O, E, St, F>(f: F) -> Parser<'a, I, O, E, St>
+where
+    I: Clone + 'a,
+    O: 'a,
+    E: 'a,
+    St: 'a,
+    F: FnOnce(Parser<'a, I, O, E, St>) -> Parser<'a, I, O, E, St>,
+{
+    let knot = Rc::new(OnceCell::<Parser<'a, I, O, E, St>>::new());
+    let grammar = Rc::new(Node::Recursive(OnceCell::new()));
+
+    // the grammar only holds a weak reference to itself, so that it doesn't
+    // own itself
+    let this = {
+        let knot = Rc::downgrade(&knot);
+        Parser::new(move |input, at, session| {
+            Weak::upgrade(&knot)
+                .expect("a recursive parser was used after it was dropped")
+                .get()
+                .expect("a recursive parser was used while being built")
+                .parse_with(input, at, session)
+        })
+        .with_grammar(Rc::new(Node::Recurse(Rc::downgrade(&grammar))))
+    };
+
+    let body = f(this);
+    if let Node::Recursive(cell) = &*grammar {
+        let _ = cell.set(body.node());
+    }
+    let _ = knot.set(body);
+    Parser::new(move |input, at, session| knot.get().unwrap().parse_with(input, at, session))
+        .with_grammar(grammar)
+}
+
+/// Build a recursive parser like [`recursive`], which may also be
+/// left-recursive, directly or through other parsers, so rules can be
+/// written the way they appear in a specification. A left-recursive call,
+/// i.e. one at the same offset as a call that is still running, first fails
+/// with `E::default()`; then, the result of the outer call is fed to it as a
+/// seed, over and over, for as long as that lets the outer call consume more
+/// input.
+///
+/// Growing seeds costs every call some bookkeeping in the session, so
+/// grammars without left recursion are better off with [`recursive`].
+///
+/// # Example
+///
+/// ```rust
+/// use hair::primitive::{left_recursive, unit};
+///
+/// // expr := expr '-' digit | digit
+/// let digit = || unit::<str>().filter_map(|c| c.to_digit(10).map(|d| d as i32));
+/// let expr = left_recursive(|expr| {
+///     expr.left(unit().filter(|&c| c == '-'))
+///         .then(digit())
+///         .map(|(a, b)| a - b)
+///         .or(digit())
+/// });
+///
+/// // left recursion makes `-` left-associative
+/// assert_eq!(expr.parse("9-3-2"), Ok(4));
+/// ```
+pub fn left_recursive<'a, I, O, E, St, F>(f: F) -> Parser<'a, I, O, E, St>
+where
+    I: Clone + 'a,
+    O: Clone + MaybeSync + 'a,
+    E: Clone + Default + MaybeSync + 'a,
+    St: 'a,
+    F: FnOnce(Parser<'a, I, O, E, St>) -> Parser<'a, I, O, E, St>,
+{
+    recursive(|this| {
+        let body = f(this);
+        let grammar = body.grammar.clone();
+        let seeds = MemoId::new();
+        Parser::new(move |input, at, session| grow(&body, &seeds, input, at, session))
+            .with_grammar(grammar)
+    })
+}
+
+/// The seed of a left-recursive call being grown, see [`left_recursive`].
+struct Seed<O, E> {
+    result: ParseResult<O, E>,
+    records: Records<E>,
+    /// Whether a left-recursive call was made.
+    read: bool,
+}
+
+/// Run `body` at `at`, answering left-recursive calls with the seeds in the
+/// session table `seeds`, and growing them.
+fn grow<'a, I, O, E, St>(
+    body: &Parser<'a, I, O, E, St>,
+    seeds: &MemoId<Seed<O, E>>,
+    input: I,
+    at: usize,
+    session: &mut Session<'a, E, St>,
+) -> ParseResult<O, E>
+where
+    I: Clone + 'a,
+    O: Clone + MaybeSync + 'a,
+    E: Clone + Default + MaybeSync + 'a,
+    St: 'a,
+{
+    let key = session.memo_key(at);
+    if let Some(seed) = session.memo(seeds).get_mut(&key) {
+        seed.read = true;
+        let (result, records) = (seed.result.clone(), seed.records.clone());
+        session.read_seed();
+        session.replay(records.recovered_only());
+        return result;
+    }
+
+    session.memo(seeds).insert(
+        key.clone(),
+        Seed {
+            result: Err(Error::new(E::default(), at)),
+            records: Records::new(),
+            read: false,
+        },
+    );
+
+    let (mut result, mut records) =
+        session.capture(|session| body.parse_with(input.clone(), at, session));
+
+    // grow the seed while the body makes progress with it
+    loop {
+        let Ok((_, rest)) = result else { break };
+        let seed = Seed {
+            result: result.clone(),
+            records: records.clone(),
+            read: false,
+        };
+        let read = std::mem::replace(session.memo(seeds).get_mut(&key).unwrap(), seed).read;
+        if !read {
+            break;
+        }
+
+        let (grown, grown_records) =
+            session.capture(|session| body.parse_with(input.clone(), at, session));
+        match grown {
+            Ok((_, end)) if end > rest => {
+                session.replay(records.furthest_only());
+                (result, records) = (grown, grown_records);
+            }
+            // growing further may need more input, which the seed can't
+            // stand in for
+            Err(Error {
+                recover: Recover::Incomplete { .. },
+                ..
+            }) => {
+                session.replay(records.furthest_only());
+                (result, records) = (grown, grown_records);
+                break;
+            }
+            _ => {
+                session.replay(grown_records.furthest_only());
+                break;
+            }
+        }
+    }
+
+    session.memo(seeds).remove(&key);
+    session.replay(records);
+    result
 }
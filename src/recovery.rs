@@ -0,0 +1,210 @@
+//! Strategies for recovering from errors, for use with
+//! [`Parser::recover_with`].
+//!
+//! Recovery only kicks in on fatal errors, i.e. once a parser has committed
+//! to a parsing path with [`Parser::expect`]: recoverable errors still
+//! propagate, so that alternatives can be tried. A recovered error is
+//! recorded in the [`Session`] and parsing goes on; the errors are collected
+//! by [`Parser::parse_recovering`].
+//!
+//! ## Example
+//!
+//! ```rust
+//! use hair::{primitive::just, recovery::skip_until};
+//!
+//! // once `let` is seen, the rest of the statement must follow
+//! let statement = just("let ")
+//!     .right(just("x").expect())
+//!     .left(just(";").expect())
+//!     .recover_with(skip_until(just(";"), || "error"));
+//! let p = statement.many();
+//!
+//! let (output, errors) = p.parse_recovering("let x;let 1;let x;");
+//! assert_eq!(output, Some(vec!["x", "error", "x"]));
+//! assert_eq!(errors.len(), 1);
+//! assert_eq!(errors[0].at(), 10);
+//! ```
+
+use crate::{sync::Rc, Error, MaybeSync, Parser, Session, Slice};
+
+/// A way of recovering from a fatal error. It is given the parser that failed,
+/// the input, the offset the parser started at and the error, and either
+/// produces an output and the offset to continue from, or gives up.
+pub struct Strategy<'a, I, O, E, St = ()> {
+    recover: Rc<RecoverFn<'a, I, O, E, St>>,
+}
+
+#[cfg(not(feature = "sync"))]
+type RecoverFn<'a, I, O, E, St> = dyn Fn(&Parser<'a, I, O, E, St>, I, usize, &Error<E>, &mut Session<'a, E, St>) -> Option<(O, usize)>
+    + 'a;
+
+#[cfg(feature = "sync")]
+type RecoverFn<'a, I, O, E, St> = dyn Fn(&Parser<'a, I, O, E, St>, I, usize, &Error<E>, &mut Session<'a, E, St>) -> Option<(O, usize)>
+    + Send
+    + Sync
+    + 'a;
+
+impl<'a, I, O, E, St> Clone for Strategy<'a, I, O, E, St> {
+    fn clone(&self) -> Self {
+        Self {
+            recover: Rc::clone(&self.recover),
+        }
+    }
+}
+
+impl<'a, I, O, E, St> Strategy<'a, I, O, E, St> {
+    pub fn new<R>(recover: R) -> Self
+    where
+        R: Fn(
+                &Parser<'a, I, O, E, St>,
+                I,
+                usize,
+                &Error<E>,
+                &mut Session<'a, E, St>,
+            ) -> Option<(O, usize)>
+            + MaybeSync
+            + 'a,
+    {
+        Self {
+            recover: Rc::new(recover),
+        }
+    }
+
+    pub(crate) fn recover(
+        &self,
+        parser: &Parser<'a, I, O, E, St>,
+        input: I,
+        at: usize,
+        err: &Error<E>,
+        session: &mut Session<'a, E, St>,
+    ) -> Option<(O, usize)> {
+        (self.recover)(parser, input, at, err, session)
+    }
+}
+
+/// Skip input from where the error occurred until `until` succeeds,
+/// consuming its match, and output `fallback()`. Gives up at the end of the
+/// input.
+///
+/// This suits constructs with a terminator, e.g. skipping to the `;` that
+/// ends a broken statement.
+pub fn skip_until<'a, S, O, U, E, E1, St, F>(
+    until: Parser<'a, &'a S, U, E1, St>,
+    fallback: F,
+) -> Strategy<'a, &'a S, O, E, St>
+where
+    S: Slice<'a> + ?Sized,
+    U: 'a,
+    E1: 'a,
+    St: 'a,
+    F: Fn() -> O + MaybeSync + 'a,
+{
+    Strategy::new(move |_, input: &'a S, at, err, session| {
+        let mut at = at.max(err.at);
+        loop {
+            if let Ok((_, rest)) = session.scratch(|session| until.parse_with(input, at, session)) {
+                return Some((fallback(), rest));
+            }
+
+            session.read_to(at + 1);
+            let (_, len) = input.index_from(at).first()?;
+            at += len;
+        }
+    })
+}
+
+/// Skip one unit of input at a time, retrying the parser after each, until it
+/// succeeds. Gives up when `until` would succeed, or at the end of the input.
+///
+/// This suits stray input in front of something valid, e.g. an unexpected
+/// token before an expression.
+pub fn skip_then_retry_until<'a, S, O, U, E, E1, St>(
+    until: Parser<'a, &'a S, U, E1, St>,
+) -> Strategy<'a, &'a S, O, E, St>
+where
+    S: Slice<'a> + ?Sized,
+    O: 'a,
+    U: 'a,
+    E: 'a,
+    E1: 'a,
+    St: 'a,
+{
+    Strategy::new(move |parser, input: &'a S, mut at, _, session| loop {
+        if session
+            .scratch(|session| until.parse_with(input, at, session))
+            .is_ok()
+        {
+            return None;
+        }
+
+        session.read_to(at + 1);
+        let (_, len) = input.index_from(at).first()?;
+        at += len;
+
+        let checkpoint = session.checkpoint();
+        match parser.parse_with(input, at, session) {
+            Ok(ok) => return Some(ok),
+            Err(_) => session.rewind(checkpoint),
+        }
+    })
+}
+
+/// Skip a delimited block of input, such as a parenthesized expression,
+/// and output `fallback()`. The parser that failed must have started at
+/// `open`; input is skipped up to the `close` that balances it, taking any of
+/// the `others` pairs of delimiters into account.
+///
+/// Gives up if the delimiters are unbalanced, e.g. `(]`, or at the end of the
+/// input.
+pub fn nested_delimiters<'a, 'b: 'a, S, O, E, St, F, const N: usize>(
+    open: &'b S,
+    close: &'b S,
+    others: [(&'b S, &'b S); N],
+    fallback: F,
+) -> Strategy<'a, &'a S, O, E, St>
+where
+    S: Slice<'a> + PartialEq<S> + MaybeSync + ?Sized,
+    F: Fn() -> O + MaybeSync + 'a,
+{
+    let starts_with = |input: &'a S, at: usize, delimiter: &'a S| {
+        input.is_boundary(at + delimiter.len())
+            && input.index_between(at, at + delimiter.len()) == delimiter
+    };
+
+    Strategy::new(move |_, input: &'a S, mut at, _, session| {
+        let pairs = || std::iter::once((open, close)).chain(others.iter().copied());
+        let longest = pairs()
+            .map(|(open, close)| open.len().max(close.len()).max(1))
+            .max()
+            .unwrap();
+
+        session.read_to(at + open.len().max(1));
+        if !starts_with(input, at, open) {
+            return None;
+        }
+
+        let mut closing: Vec<&'a S> = Vec::new();
+        loop {
+            session.read_to(at + longest);
+            if let Some((delimiter, close)) = pairs().find(|(open, _)| starts_with(input, at, open))
+            {
+                closing.push(close);
+                at += delimiter.len();
+            } else if let Some((_, delimiter)) =
+                pairs().find(|(_, close)| starts_with(input, at, close))
+            {
+                if closing.pop() != Some(delimiter) {
+                    return None;
+                }
+
+                at += delimiter.len();
+                if closing.is_empty() {
+                    return Some((fallback(), at));
+                }
+            } else {
+                let (_, len) = input.index_from(at).first()?;
+                at += len;
+            }
+        }
+    })
+}
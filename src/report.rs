@@ -0,0 +1,278 @@
+//! Rendering of parse errors for humans. See [`Report`].
+//!
+//! ## Example
+//!
+//! ```rust
+//! use hair::{primitive::just, report::Report};
+//!
+//! let source = "let x = 1;\nlet y 2;";
+//! let p = just("let x = 1;\nlet y ").then(just("="));
+//! let (_, at) = p.parse(source).unwrap_err();
+//!
+//! let report = Report::new(source, at..at + 1, "unexpected number")
+//!     .with_file("main.txt")
+//!     .with_primary_label("expected `=`")
+//!     .with_label(4..5, "in the definition of `x`")
+//!     .with_note("definitions look like `let name = value;`");
+//!
+//! assert_eq!(
+//!     report.to_string(),
+//!     "\
+//! error: unexpected number
+//!  --> main.txt:2:7
+//!   |
+//! 1 | let x = 1;
+//!   |     - in the definition of `x`
+//! 2 | let y 2;
+//!   |       ^ expected `=`
+//!   |
+//!   = note: definitions look like `let name = value;`
+//! "
+//! );
+//! ```
+
+use std::{fmt, ops::Range};
+
+use crate::{location::LineIndex, Error};
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+
+/// A diagnostic for an error in some source text, rendered in the style of
+/// rustc by its [`Display`](fmt::Display) implementation: a message, followed
+/// by the lines of the source that the error's span and any additional
+/// labels cover, underlined, and finally any notes.
+///
+/// Spans are byte ranges into the source, like the offsets returned by
+/// parsers. A zero-width span is underlined with a single caret.
+#[derive(Clone, Debug)]
+pub struct Report<'s> {
+    source: &'s str,
+    file: Option<String>,
+    message: String,
+    /// The primary label comes first.
+    labels: Vec<Label>,
+    notes: Vec<String>,
+    color: bool,
+}
+
+#[derive(Clone, Debug)]
+struct Label {
+    span: Range<usize>,
+    text: Option<String>,
+}
+
+impl<'s> Report<'s> {
+    /// Report an error at `span` in `source`.
+    pub fn new(source: &'s str, span: Range<usize>, message: impl fmt::Display) -> Self {
+        Self {
+            source,
+            file: None,
+            message: message.to_string(),
+            labels: vec![Label { span, text: None }],
+            notes: Vec::new(),
+            color: false,
+        }
+    }
+
+    /// Report an [`Error`] returned by a parser, using the inner error's
+    /// [`Display`](fmt::Display) implementation as the message.
+    pub fn from_error<E: fmt::Display>(source: &'s str, error: &Error<E>) -> Self {
+        Self::from_error_with(source, error, E::to_string)
+    }
+
+    /// Report an [`Error`] returned by a parser, formatting the inner error
+    /// with `f`. The error's [span](Error::span) is underlined, and its
+    /// [context](Error::context) is noted, like
+    /// `while parsing object member at 3:5`.
+    pub fn from_error_with<E, F>(source: &'s str, error: &Error<E>, f: F) -> Self
+    where
+        F: FnOnce(&E) -> String,
+    {
+        let index = LineIndex::new(source);
+        error.context().iter().fold(
+            Self::new(source, error.span(), f(error.inner())),
+            |report, context| {
+                let at = index.location(context.span.start);
+                report.with_note(format!("while parsing {} at {at}", context.label))
+            },
+        )
+    }
+
+    /// Report an [`Error`] whose inner error describes what was expected,
+    /// such as a [`Label`](crate::Label), with a message like
+    /// `expected value, found ']'`.
+    pub fn expected<E: fmt::Display>(source: &'s str, error: &Error<E>) -> Self {
+        Self::from_error_with(source, error, |expected| {
+            match source
+                .get(error.at()..)
+                .and_then(|rest| rest.chars().next())
+            {
+                Some(c) => format!("expected {expected}, found {c:?}"),
+                None => format!("expected {expected}, found end of input"),
+            }
+        })
+    }
+
+    /// Name the file that the source came from, which is shown alongside the
+    /// error's location.
+    pub fn with_file(self, file: impl Into<String>) -> Self {
+        Self {
+            file: Some(file.into()),
+            ..self
+        }
+    }
+
+    /// Set the text shown next to the underline of the error's span.
+    pub fn with_primary_label(mut self, text: impl fmt::Display) -> Self {
+        self.labels[0].text = Some(text.to_string());
+        self
+    }
+
+    /// Underline another span with an explanation, e.g. to point out an
+    /// unclosed delimiter.
+    pub fn with_label(mut self, span: Range<usize>, text: impl fmt::Display) -> Self {
+        self.labels.push(Label {
+            span,
+            text: Some(text.to_string()),
+        });
+        self
+    }
+
+    /// Add a note, shown after the source snippet.
+    pub fn with_note(mut self, note: impl fmt::Display) -> Self {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    /// Colour the output with ANSI escape codes. Off by default.
+    pub fn with_color(self, color: bool) -> Self {
+        Self { color, ..self }
+    }
+
+    fn paint<'c>(&self, color: &'c str) -> &'c str {
+        if self.color {
+            color
+        } else {
+            ""
+        }
+    }
+}
+
+impl fmt::Display for Report<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let index = LineIndex::new(self.source);
+        let (bold, red, blue, reset) = (
+            self.paint(BOLD),
+            self.paint(RED),
+            self.paint(BLUE),
+            self.paint(RESET),
+        );
+
+        // the lines each label starts and ends on, clamped to the source
+        let lines: Vec<(usize, usize)> = self
+            .labels
+            .iter()
+            .map(|label| {
+                let start = label.span.start.min(self.source.len());
+                let end = label.span.end.clamp(start, self.source.len());
+                (index.line(start), index.line(end))
+            })
+            .collect();
+
+        let mut shown: Vec<usize> = lines
+            .iter()
+            .flat_map(|&(first, last)| first..=last)
+            .collect();
+        shown.sort_unstable();
+        shown.dedup();
+
+        let gutter = shown.last().map_or(1, |line| line.to_string().len());
+        let pad = " ".repeat(gutter);
+
+        writeln!(f, "{red}error{reset}{bold}: {}{reset}", self.message)?;
+
+        let location = index.location(self.labels[0].span.start.min(self.source.len()));
+        write!(f, "{pad}{blue}-->{reset} ")?;
+        if let Some(file) = &self.file {
+            write!(f, "{file}:")?;
+        }
+        writeln!(f, "{location}")?;
+        writeln!(f, "{pad} {blue}|{reset}")?;
+
+        for (i, &line) in shown.iter().enumerate() {
+            if i > 0 && shown[i - 1] + 1 < line {
+                writeln!(f, "{blue}...{reset}")?;
+            }
+
+            let range = index.line_range(line);
+            let text = &self.source[range.clone()];
+            writeln!(
+                f,
+                "{blue}{line:>gutter$} |{reset} {}",
+                text.replace('\t', "    ")
+            )?;
+
+            for (j, label) in self.labels.iter().enumerate() {
+                let (first, last) = lines[j];
+                if !(first..=last).contains(&line) {
+                    continue;
+                }
+
+                // the part of this line that the label covers, widened to
+                // whole chars
+                let start =
+                    floor_boundary(self.source, label.span.start.clamp(range.start, range.end));
+                let end = ceil_boundary(self.source, label.span.end.clamp(start, range.end));
+                let offset = width(&self.source[range.start..start]);
+                let len = width(&self.source[start..end]).max(1);
+
+                let (mark, color) = if j == 0 { ('^', red) } else { ('-', blue) };
+                write!(
+                    f,
+                    "{pad} {blue}|{reset} {}{color}{}",
+                    " ".repeat(offset),
+                    mark.to_string().repeat(len)
+                )?;
+                match &label.text {
+                    Some(text) if line == last && !text.is_empty() => {
+                        writeln!(f, " {text}{reset}")?
+                    }
+                    _ => writeln!(f, "{reset}")?,
+                }
+            }
+        }
+
+        if !self.notes.is_empty() {
+            writeln!(f, "{pad} {blue}|{reset}")?;
+        }
+        for note in &self.notes {
+            writeln!(f, "{pad} {blue}={reset} {bold}note{reset}: {note}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The width of some text on a terminal, counting tabs as four columns.
+fn width(text: &str) -> usize {
+    text.chars().map(|c| if c == '\t' { 4 } else { 1 }).sum()
+}
+
+/// The char boundary of `text` at or before `offset`.
+fn floor_boundary(text: &str, mut offset: usize) -> usize {
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+/// The char boundary of `text` at or after `offset`.
+fn ceil_boundary(text: &str, mut offset: usize) -> usize {
+    while !text.is_char_boundary(offset) {
+        offset += 1;
+    }
+    offset
+}
@@ -0,0 +1,618 @@
+//! See [`Session`].
+
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{incremental::Reuse, trace::Tracer, Error, MaybeSync, ParseResult, Recover};
+
+/// Bookkeeping for a single run of a parser, passed down to every parser
+/// through [`Parser::parse_with`](crate::Parser::parse_with).
+///
+/// Combinators like [`Parser::or`](crate::Parser::or) and
+/// [`Parser::optional`](crate::Parser::optional) backtrack over recoverable
+/// errors, throwing them away. Before doing so, they
+/// [record](Session::record) them in the session, so that when the parse
+/// fails, the error that got furthest into the input can be reported, rather
+/// than the one at the last point of backtracking.
+///
+/// The session also collects the errors that parsers recovered from with
+/// [`Parser::recover_with`](crate::Parser::recover_with), holds the user
+/// state of the parse, an `St`, see [`Session::state`], and the tables of
+/// [memoized](crate::Parser::memoize) parsers, which live as long as it does.
+///
+/// A session is created by the entry points, e.g. [`Parser::parse`](crate::Parser::parse).
+/// Parsers written with [`Parser::new`](crate::Parser::new) should pass their
+/// session along to the parsers they call.
+pub struct Session<'a, E, St = ()> {
+    shared: Shared<'a>,
+    state: State<St>,
+    records: Records<E>,
+}
+
+/// The part of a session that doesn't depend on the error type, and so is
+/// shared with nested sessions.
+#[derive(Default)]
+struct Shared<'a> {
+    /// The number of times a left-recursive call was answered with a seed,
+    /// see [`left_recursive`](crate::primitive::left_recursive).
+    seed_reads: u64,
+    /// The number of times a result was marked as holding offsets, see
+    /// [`Session::read_offset`].
+    offset_reads: u64,
+    /// The number of times a result was marked as depending on the input
+    /// before it, see [`Session::read_behind`].
+    behind_reads: u64,
+    /// See [`Session::is_partial`].
+    partial: bool,
+    /// See [`Parser::parse_traced`](crate::Parser::parse_traced).
+    tracer: Option<Tracer>,
+    /// See [`Session::read_to`].
+    read: usize,
+    /// See [`Session::memo`].
+    memo: Memo<'a>,
+    /// The previous parse whose memoized results may be reused, see
+    /// [`Parser::reparse`](crate::Parser::reparse).
+    reuse: Option<Reuse<'a>>,
+    /// The indentation of the blocks being parsed, innermost last, see
+    /// [`indent`](crate::indent).
+    indents: Vec<usize>,
+    /// The end of the input, if it was cut short, see [`Session::truncated`].
+    end: Option<usize>,
+}
+
+/// The user state of a session, see [`Session::state`].
+struct State<St> {
+    /// Only taken while a nested session has it, see [`Session::capture`].
+    value: Option<St>,
+    /// The states before the [scopes](crate::Parser::with_state_scope) that
+    /// succeeded, oldest first, to restore when rewinding past them.
+    scopes: Vec<St>,
+}
+
+impl<St> State<St> {
+    fn new(value: St) -> Self {
+        Self {
+            value: Some(value),
+            scopes: Vec::new(),
+        }
+    }
+
+    fn take(&mut self) -> Self {
+        Self {
+            value: self.value.take(),
+            scopes: std::mem::take(&mut self.scopes),
+        }
+    }
+}
+
+/// Errors recorded in a session.
+#[derive(Clone)]
+pub(crate) struct Records<E> {
+    /// Discarded errors, all at the furthest offset seen so far.
+    furthest: Vec<Error<E>>,
+    /// Errors that were recovered from, in the order they occurred.
+    recovered: Vec<Error<E>>,
+}
+
+impl<E> Records<E> {
+    pub(crate) fn new() -> Self {
+        Self {
+            furthest: Vec::new(),
+            recovered: Vec::new(),
+        }
+    }
+
+    /// Convert every error, e.g. into another error type.
+    pub(crate) fn map<E1, F>(self, f: F) -> Records<E1>
+    where
+        F: Fn(Error<E>) -> Error<E1>,
+    {
+        Records {
+            furthest: self.furthest.into_iter().map(&f).collect(),
+            recovered: self.recovered.into_iter().map(&f).collect(),
+        }
+    }
+
+    /// Drop the errors that were recovered from, e.g. when the result they
+    /// belong to is thrown away.
+    pub(crate) fn furthest_only(self) -> Self {
+        Self {
+            furthest: self.furthest,
+            recovered: Vec::new(),
+        }
+    }
+
+    /// Move every error by `delta`, e.g. after an edit of the input before
+    /// them.
+    pub(crate) fn shift(self, delta: isize) -> Self {
+        self.map(|err| err.shift(delta))
+    }
+
+    /// Drop the discarded errors at `at` or before it, e.g. when a
+    /// [label](crate::Parser::labelled) stands for them.
+    pub(crate) fn beyond(self, at: usize) -> Self {
+        Self {
+            furthest: self
+                .furthest
+                .into_iter()
+                .filter(|err| err.at > at)
+                .collect(),
+            recovered: self.recovered,
+        }
+    }
+
+    /// Drop the discarded errors, e.g. when the result they belong to is
+    /// reused, and they have been recorded already.
+    pub(crate) fn recovered_only(self) -> Self {
+        Self {
+            furthest: Vec::new(),
+            recovered: self.recovered,
+        }
+    }
+}
+
+/// A point in a [`Session`] to backtrack to, see [`Session::backtrack`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Checkpoint {
+    recovered: usize,
+    scopes: usize,
+}
+
+/// The tables that parsers keep for a parse by where they ran, see
+/// [`MemoKey`], one for each parser:
+/// the results of [memoized](crate::Parser::memoize) parsers, and the seeds
+/// of [left-recursive](crate::primitive::left_recursive) ones.
+#[derive(Default)]
+pub(crate) struct Memo<'a> {
+    tables: HashMap<u64, Box<dyn MaybeSync + 'a>>,
+}
+
+/// Where a parser ran: its offset, and what else a parser there may depend
+/// on, as the same parser at the same offset can see different input, or be
+/// in different blocks.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct MemoKey {
+    pub(crate) at: usize,
+    /// See [`Session::truncated`].
+    pub(crate) end: Option<usize>,
+    /// See [`Session::indents`].
+    pub(crate) indents: Vec<usize>,
+}
+
+/// Identifies the table of a parser, which holds `T`s by [`MemoKey`].
+pub(crate) struct MemoId<T> {
+    id: u64,
+    // invariant, so that no id can be used for the table of another type
+    table: PhantomData<fn(T) -> T>,
+}
+
+impl<T> MemoId<T> {
+    /// An id that no other parser has.
+    pub(crate) fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            table: PhantomData,
+        }
+    }
+}
+
+impl<'a> Memo<'a> {
+    pub(crate) fn get<T: MaybeSync + 'a>(&self, id: &MemoId<T>) -> Option<&HashMap<MemoKey, T>> {
+        let table = &**self.tables.get(&id.id)?;
+        // SAFETY: the table of `id` was inserted by `get_mut` as a
+        // `HashMap<MemoKey, T>`, as ids are unique and never change their type
+        Some(unsafe { &*(table as *const dyn MaybeSync as *const HashMap<MemoKey, T>) })
+    }
+
+    pub(crate) fn get_mut<T: MaybeSync + 'a>(
+        &mut self,
+        id: &MemoId<T>,
+    ) -> &mut HashMap<MemoKey, T> {
+        let table = &mut **self
+            .tables
+            .entry(id.id)
+            .or_insert_with(|| Box::new(HashMap::<MemoKey, T>::new()));
+        // SAFETY: see `get`
+        unsafe { &mut *(table as *mut dyn MaybeSync as *mut HashMap<MemoKey, T>) }
+    }
+}
+
+impl<'a, E> Session<'a, E> {
+    pub fn new() -> Self {
+        Self::with_state(())
+    }
+}
+
+impl<'a, E, St> Session<'a, E, St> {
+    /// A session for a parse with the user state `state`, see
+    /// [`Session::state`].
+    pub fn with_state(state: St) -> Self {
+        Self {
+            shared: Shared {
+                seed_reads: 0,
+                offset_reads: 0,
+                behind_reads: 0,
+                partial: false,
+                tracer: None,
+                read: 0,
+                memo: Memo::default(),
+                reuse: None,
+                indents: Vec::new(),
+                end: None,
+            },
+            state: State::new(state),
+            records: Records::new(),
+        }
+    }
+
+    /// Count a left-recursive call answered with a seed. Results that depend
+    /// on seeds are provisional, and must not be memoized.
+    pub(crate) fn read_seed(&mut self) {
+        self.shared.seed_reads += 1;
+    }
+
+    pub(crate) fn seed_reads(&self) -> u64 {
+        self.shared.seed_reads
+    }
+
+    /// The user state of the parse, e.g. a symbol table, given to
+    /// [`Parser::parse_with_state`](crate::Parser::parse_with_state). Its type
+    /// is that of the parsers, so parsers expecting state of another type
+    /// can't be part of the parse.
+    pub fn state(&mut self) -> &mut St {
+        self.state
+            .value
+            .as_mut()
+            .expect("the state is only taken by nested sessions")
+    }
+
+    /// Take the user state out of the session, once the parse is done.
+    pub(crate) fn take_state(&mut self) -> St {
+        self.state
+            .value
+            .take()
+            .expect("the state is only taken by nested sessions")
+    }
+
+    /// End a [scope](crate::Parser::with_state_scope) that started at
+    /// `checkpoint`, with the state `before` it: restore that state if the
+    /// scope failed, and otherwise keep it, to be restored when the session
+    /// is rewound to `checkpoint` or before it.
+    pub(crate) fn end_scope(&mut self, checkpoint: Checkpoint, before: St, ok: bool) {
+        self.state.scopes.truncate(checkpoint.scopes);
+        if ok {
+            self.state.scopes.push(before);
+        } else {
+            self.state.value = Some(before);
+        }
+    }
+
+    /// Mark the result being parsed as depending on the offset it's at, as
+    /// when its output holds an offset or a span of the input. Parsers
+    /// written with [`Parser::new`](crate::Parser::new) that output offsets
+    /// should call this, so that [`Parser::reparse`](crate::Parser::reparse)
+    /// doesn't reuse their results where an edit moved them.
+    pub fn read_offset(&mut self) {
+        self.shared.offset_reads += 1;
+    }
+
+    pub(crate) fn offset_reads(&self) -> u64 {
+        self.shared.offset_reads
+    }
+
+    /// Mark the result being parsed as depending on the input before where
+    /// it's at, as when it checks that it's at the start of a line. Parsers
+    /// written with [`Parser::new`](crate::Parser::new) that look behind
+    /// should call this, so that [`Parser::reparse`](crate::Parser::reparse)
+    /// only reuses their results before an edit.
+    pub fn read_behind(&mut self) {
+        self.shared.behind_reads += 1;
+    }
+
+    pub(crate) fn behind_reads(&self) -> u64 {
+        self.shared.behind_reads
+    }
+
+    /// Mark the input before `end` as read. Parsers written with
+    /// [`Parser::new`](crate::Parser::new) that look at the input directly,
+    /// rather than through other parsers, should mark everything their result
+    /// depends on, including the end of the input, as at `input.len() + 1`.
+    /// This tells [`Parser::reparse`](crate::Parser::reparse) which results
+    /// an edit can change.
+    pub fn read_to(&mut self, end: usize) {
+        self.shared.read = self.shared.read.max(end);
+    }
+
+    /// Run `f`, and return how far from `at` it read the input, see
+    /// [`Session::read_to`]. The caller marks it as read, if it should be.
+    pub(crate) fn reading<T>(&mut self, at: usize, f: impl FnOnce(&mut Self) -> T) -> (T, usize) {
+        let outer = std::mem::replace(&mut self.shared.read, at);
+        let t = f(self);
+        let read = std::mem::replace(&mut self.shared.read, outer);
+        (t, read)
+    }
+
+    /// The key of a parser that runs at `at` in the tables of [`Memo`].
+    pub(crate) fn memo_key(&self, at: usize) -> MemoKey {
+        MemoKey {
+            at,
+            end: self.shared.end,
+            indents: self.shared.indents.clone(),
+        }
+    }
+
+    /// The table `id` of a parser in this parse, see [`Memo`].
+    pub(crate) fn memo<T: MaybeSync + 'a>(&mut self, id: &MemoId<T>) -> &mut HashMap<MemoKey, T> {
+        self.shared.memo.get_mut(id)
+    }
+
+    /// Take the tables out of the session, e.g. to keep them for
+    /// [`Parser::reparse`](crate::Parser::reparse).
+    pub(crate) fn take_memo(&mut self) -> Memo<'a> {
+        std::mem::take(&mut self.shared.memo)
+    }
+
+    pub(crate) fn reuse(&self) -> Option<&Reuse<'a>> {
+        self.shared.reuse.as_ref()
+    }
+
+    pub(crate) fn set_reuse(&mut self, reuse: Option<Reuse<'a>>) {
+        self.shared.reuse = reuse;
+    }
+
+    /// The indentation of the innermost block being parsed, or 0 outside of
+    /// any block.
+    pub(crate) fn indent(&self) -> usize {
+        self.shared.indents.last().copied().unwrap_or(0)
+    }
+
+    pub(crate) fn indents(&self) -> &[usize] {
+        &self.shared.indents
+    }
+
+    pub(crate) fn push_indent(&mut self, indent: usize) {
+        self.shared.indents.push(indent);
+    }
+
+    pub(crate) fn pop_indent(&mut self) {
+        self.shared.indents.pop();
+    }
+
+    /// The attempts being recorded, if the parse is traced.
+    pub(crate) fn tracer(&mut self) -> Option<&mut Tracer> {
+        self.shared.tracer.as_mut()
+    }
+
+    pub(crate) fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.shared.tracer = tracer;
+    }
+
+    pub(crate) fn take_tracer(&mut self) -> Option<Tracer> {
+        self.shared.tracer.take()
+    }
+
+    /// Whether the input is only partially available, with more of it to
+    /// come after its end, as when parsing a [`stream`](crate::stream).
+    pub fn is_partial(&self) -> bool {
+        self.shared.partial
+    }
+
+    pub(crate) fn set_partial(&mut self, partial: bool) {
+        self.shared.partial = partial;
+    }
+
+    /// Run `f` on the input cut off at `end`, such as a frame of known
+    /// length. It certainly ends there, even if the input it was cut from is
+    /// partial, and the results memoized in it are kept apart from those of
+    /// the whole input.
+    pub(crate) fn truncated<T>(&mut self, end: usize, f: impl FnOnce(&mut Self) -> T) -> T {
+        let partial = std::mem::replace(&mut self.shared.partial, false);
+        let outer = self.shared.end.replace(end);
+        let t = f(self);
+        self.shared.partial = partial;
+        self.shared.end = outer;
+        t
+    }
+
+    /// The error at `at` of a parser that needs at least `needed` more units
+    /// of input past its end: [`Recover::Incomplete`] if the input is
+    /// [partial](Session::is_partial), and otherwise a recoverable error.
+    pub fn out_of_input(&self, inner: E, at: usize, needed: usize) -> Error<E> {
+        let err = Error::new(inner, at);
+        if self.is_partial() {
+            Error {
+                recover: Recover::Incomplete { needed },
+                ..err
+            }
+        } else {
+            err
+        }
+    }
+
+    /// Remember an error that is about to be discarded. Only errors at the
+    /// furthest offset seen so far are kept.
+    pub fn record(&mut self, err: Error<E>) {
+        let furthest = &mut self.records.furthest;
+        match furthest.first().map(|first| err.at.cmp(&first.at)) {
+            Some(std::cmp::Ordering::Less) => {}
+            Some(std::cmp::Ordering::Equal) => furthest.push(err),
+            Some(std::cmp::Ordering::Greater) | None => *furthest = vec![err],
+        }
+    }
+
+    /// Remember an error that was recovered from, after which parsing went
+    /// on. See [`Parser::parse_recovering`](crate::Parser::parse_recovering).
+    pub fn recovered(&mut self, err: Error<E>) {
+        self.records.recovered.push(err);
+    }
+
+    /// The error of a filter at `at`, whose inner parser either failed with
+    /// `err`, or succeeded with an output that was rejected. An
+    /// [`Recover::Incomplete`] error is passed on, since more input may
+    /// change the output, and any other is recorded and replaced with
+    /// `inner`.
+    pub(crate) fn reject(&mut self, err: Option<Error<E>>, inner: E, at: usize) -> Error<E> {
+        match err {
+            Some(
+                err @ Error {
+                    recover: Recover::Incomplete { .. },
+                    ..
+                },
+            ) => err,
+            Some(err) => {
+                self.record(err);
+                Error::new(inner, at)
+            }
+            None => Error::new(inner, at),
+        }
+    }
+
+    /// Mark the current point, before trying a parser that may be
+    /// backtracked over.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            recovered: self.records.recovered.len(),
+            scopes: self.state.scopes.len(),
+        }
+    }
+
+    /// Backtrack over `err`, returning to `checkpoint`: the error is
+    /// [recorded](Session::record), and errors recovered from since the
+    /// checkpoint are forgotten, since the input they were found in will be
+    /// parsed again.
+    pub fn backtrack(&mut self, checkpoint: Checkpoint, err: Error<E>) {
+        self.rewind(checkpoint);
+        self.record(err);
+    }
+
+    /// Forget the errors recovered from since `checkpoint`, and undo the
+    /// changes to the state made in [scopes](crate::Parser::with_state_scope)
+    /// since.
+    pub fn rewind(&mut self, checkpoint: Checkpoint) {
+        self.records.recovered.truncate(checkpoint.recovered);
+        if let Some(before) = self.state.scopes.drain(checkpoint.scopes..).next() {
+            self.state.value = Some(before);
+        }
+    }
+
+    /// Run `f` with a session for parsers of another error type, such as the
+    /// inner parser of [`Parser::map_err`](crate::Parser::map_err). Errors
+    /// recorded in the inner session are converted with `map` and recorded in
+    /// this one.
+    pub fn nest<E1, T, M, F>(&mut self, map: M, f: F) -> T
+    where
+        M: Fn(E1) -> E,
+        F: FnOnce(&mut Session<'a, E1, St>) -> T,
+    {
+        let (t, records) = self.capture(f);
+        self.replay(records.map(|err| err.map(&map)));
+
+        t
+    }
+
+    /// Run `f` with a session for parsers of another error type, throwing
+    /// away anything recorded in it. This is for parsers that only look ahead,
+    /// whose failures are not errors in the input.
+    pub fn scratch<E1, T, F>(&mut self, f: F) -> T
+    where
+        F: FnOnce(&mut Session<'a, E1, St>) -> T,
+    {
+        self.capture(f).0
+    }
+
+    /// Run `f` with a nested session, returning what was recorded in it
+    /// rather than recording it in this session.
+    pub(crate) fn capture<E1, T, F>(&mut self, f: F) -> (T, Records<E1>)
+    where
+        F: FnOnce(&mut Session<'a, E1, St>) -> T,
+    {
+        let mut inner = Session {
+            shared: std::mem::take(&mut self.shared),
+            state: self.state.take(),
+            records: Records::new(),
+        };
+        let t = f(&mut inner);
+        self.shared = inner.shared;
+        self.state = inner.state;
+
+        (t, inner.records)
+    }
+
+    /// Run `f` with a session without user state, for parsers that don't use
+    /// it, see [`Parser::stateful`](crate::Parser::stateful). What they record
+    /// is recorded in this session.
+    pub(crate) fn stateless<T, F>(&mut self, f: F) -> T
+    where
+        F: FnOnce(&mut Session<'a, E>) -> T,
+    {
+        let mut inner = Session {
+            shared: std::mem::take(&mut self.shared),
+            state: State::new(()),
+            records: std::mem::replace(&mut self.records, Records::new()),
+        };
+        let t = f(&mut inner);
+        self.shared = inner.shared;
+        self.records = inner.records;
+
+        t
+    }
+
+    /// Record everything in `records`, as if it had happened in this session.
+    pub(crate) fn replay(&mut self, records: Records<E>) {
+        for err in records.furthest {
+            self.record(err);
+        }
+        self.records.recovered.extend(records.recovered);
+    }
+
+    /// Consume the session after the top-level parser returned `result`,
+    /// collecting the errors at the furthest offset reached. The error
+    /// returned by the parser itself comes last, if it is among them.
+    pub(crate) fn finish<O>(
+        mut self,
+        result: ParseResult<O, E>,
+    ) -> Result<(O, usize), Vec<Error<E>>> {
+        match result {
+            Ok(ok) => Ok(ok),
+            Err(err) => {
+                self.record(err);
+                Err(self.records.furthest)
+            }
+        }
+    }
+
+    /// Like [`Session::finish`], but only keep the error that is reported
+    /// by entry points like [`Parser::parse`](crate::Parser::parse): the
+    /// parser's own, if it got furthest, or else the last one recorded.
+    pub(crate) fn finish_furthest<O>(self, result: ParseResult<O, E>) -> ParseResult<O, E> {
+        self.finish(result)
+            .map_err(|mut errors| errors.pop().expect("a failed parse records its error"))
+    }
+
+    /// Like [`Session::finish`], but also collect the errors that were
+    /// recovered from. On failure, the furthest error comes last.
+    pub(crate) fn finish_recovering<O>(
+        mut self,
+        result: ParseResult<O, E>,
+    ) -> (Option<O>, Vec<Error<E>>) {
+        let mut errors = std::mem::take(&mut self.records.recovered);
+        match self.finish(result) {
+            Ok((o, _)) => (Some(o), errors),
+            Err(mut furthest) => {
+                errors.extend(furthest.pop());
+                (None, errors)
+            }
+        }
+    }
+}
+
+impl<E> Default for Session<'_, E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
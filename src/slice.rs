@@ -12,11 +12,17 @@ pub trait Slice<'a> {
     fn first(&'a self) -> Option<(Self::Item, usize)>;
 
     /// `slice[..n]`.
-    fn index_to(&'a self, n: usize) -> &Self;
+    fn index_to(&'a self, n: usize) -> &'a Self;
     /// `slice[n..]`.
-    fn index_from(&'a self, n: usize) -> &Self;
+    fn index_from(&'a self, n: usize) -> &'a Self;
     /// `slice[n..o]`.
-    fn index_between(&'a self, n: usize, o: usize) -> &Self;
+    fn index_between(&'a self, n: usize, o: usize) -> &'a Self;
+
+    /// Whether the slice can be split at `n`, e.g. whether `n` is a char
+    /// boundary of a `str`.
+    fn is_boundary(&'a self, n: usize) -> bool {
+        n <= self.len()
+    }
 }
 
 impl<'a, T: 'a> Slice<'a> for [T] {
@@ -34,15 +40,15 @@ impl<'a, T: 'a> Slice<'a> for [T] {
         self.first().map(|t| (t, 1))
     }
 
-    fn index_to(&'a self, n: usize) -> &Self {
+    fn index_to(&'a self, n: usize) -> &'a Self {
         &self[..n]
     }
 
-    fn index_from(&'a self, n: usize) -> &Self {
+    fn index_from(&'a self, n: usize) -> &'a Self {
         &self[n..]
     }
 
-    fn index_between(&'a self, n: usize, o: usize) -> &Self {
+    fn index_between(&'a self, n: usize, o: usize) -> &'a Self {
         &self[n..o]
     }
 }
@@ -62,15 +68,19 @@ impl<'a> Slice<'a> for str {
         self.chars().next().map(|c| (c, c.len_utf8()))
     }
 
-    fn index_to(&'a self, n: usize) -> &Self {
+    fn index_to(&'a self, n: usize) -> &'a Self {
         &self[..n]
     }
 
-    fn index_from(&'a self, n: usize) -> &Self {
+    fn index_from(&'a self, n: usize) -> &'a Self {
         &self[n..]
     }
 
-    fn index_between(&'a self, n: usize, o: usize) -> &Self {
+    fn index_between(&'a self, n: usize, o: usize) -> &'a Self {
         &self[n..o]
     }
+
+    fn is_boundary(&'a self, n: usize) -> bool {
+        self.is_char_boundary(n)
+    }
 }
@@ -0,0 +1,264 @@
+//! Parsing input as it is read from an [`io::Read`], one item at a time.
+//!
+//! The grammar of an item parses whatever input has been read so far. While
+//! there is more to come, the input is [partial](Session::is_partial), and a
+//! parser that runs out of it fails with [`Recover::Incomplete`] rather than a
+//! recoverable error. The item is then parsed again once more input has been
+//! read. The primitives of this crate do this themselves; parsers that look at
+//! the end of the input directly should use [`Session::out_of_input`].
+//!
+//! A grammar borrows its input, so the grammar of an item is made for each
+//! attempt at it by a function of the input, usually `|_| grammar()`.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use hair::{primitive::just, stream::parse_stream, text::{self, TextError}, Parser};
+//!
+//! fn number<'a>() -> Parser<'a, &'a str, u32, TextError> {
+//!     text::integer().left(just(";").map_err(|_| TextError::Unexpected))
+//! }
+//!
+//! let input = "1;22;333;".as_bytes();
+//! let numbers: Result<Vec<_>, _> = parse_stream(input, |_| number()).collect();
+//! assert_eq!(numbers.unwrap(), vec![1, 22, 333]);
+//! ```
+//!
+//! ## Limitations
+//!
+//! Recovery strategies (see [`recovery`](crate::recovery)) don't wait for
+//! more input, and skip to the end of what has been read if their delimiter
+//! isn't found there. Tables of [`Parser::memoize`] only last for one attempt
+//! at an item.
+
+use std::{
+    io::{self, Read},
+    marker::PhantomData,
+};
+
+use crate::{Error, Parser, Recover, Session};
+
+/// How many bytes are read at least, whenever more input is needed.
+const CHUNK: usize = 8 * 1024;
+
+/// Input types that can be parsed from a stream, see [`parse_stream`].
+/// Offsets into them count bytes.
+pub trait StreamSlice {
+    /// View the bytes read so far as input. If the stream isn't `complete`,
+    /// they may end in the middle of a unit of input, which is left out.
+    fn from_buffer(buffer: &[u8], complete: bool) -> io::Result<&Self>;
+}
+
+impl StreamSlice for [u8] {
+    fn from_buffer(buffer: &[u8], _: bool) -> io::Result<&Self> {
+        Ok(buffer)
+    }
+}
+
+impl StreamSlice for str {
+    fn from_buffer(buffer: &[u8], complete: bool) -> io::Result<&Self> {
+        match std::str::from_utf8(buffer) {
+            Ok(s) => Ok(s),
+            // a character cut off at the end of what has been read so far
+            Err(err) if !complete && err.error_len().is_none() => {
+                Ok(std::str::from_utf8(&buffer[..err.valid_up_to()]).unwrap())
+            }
+            Err(err) => Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+        }
+    }
+}
+
+/// An error of a [`Stream`].
+#[derive(Debug)]
+pub enum StreamError<E> {
+    /// Reading from the stream failed, or its input isn't valid for the
+    /// input type, e.g. not UTF-8 for `str`.
+    Io(io::Error),
+    /// Parsing an item failed. The error, like that of [`Parser::parse`], is
+    /// the one that got furthest, and its offsets, including those of its
+    /// spans and context, are from the start of the stream.
+    Parse(Error<E>),
+}
+
+impl<E> From<io::Error> for StreamError<E> {
+    fn from(err: io::Error) -> Self {
+        StreamError::Io(err)
+    }
+}
+
+/// Parse items from `reader` one after another, with the grammar made by
+/// `grammar`, until the input ends. See the [module documentation](self).
+///
+/// The stream also ends after an error, or an item that consumed no input.
+pub fn parse_stream<R, S, O, E, F>(reader: R, grammar: F) -> Stream<R, S, F>
+where
+    R: Read,
+    S: StreamSlice + ?Sized,
+    F: for<'b> Fn(&'b S) -> Parser<'b, &'b S, O, E>,
+{
+    parse_stream_with_state(reader, (), grammar)
+}
+
+/// Like [`parse_stream`], but with `state` as the user state of the parse of
+/// each item in turn, see [`Parser::parse_with_state`]. It's taken back out
+/// of the stream with [`Stream::into_state`].
+///
+/// An attempt at an item that runs out of input starts over from the state
+/// before it, so the state is cloned for each attempt.
+pub fn parse_stream_with_state<R, S, O, E, St, F>(
+    reader: R,
+    state: St,
+    grammar: F,
+) -> Stream<R, S, F, St>
+where
+    R: Read,
+    S: StreamSlice + ?Sized,
+    St: Clone,
+    F: for<'b> Fn(&'b S) -> Parser<'b, &'b S, O, E, St>,
+{
+    Stream {
+        reader,
+        grammar,
+        state,
+        buffer: Vec::new(),
+        start: 0,
+        consumed: 0,
+        eof: false,
+        done: false,
+        input: PhantomData,
+    }
+}
+
+/// An iterator over the items parsed from a reader, see [`parse_stream`].
+pub struct Stream<R, S: ?Sized, F, St = ()> {
+    reader: R,
+    grammar: F,
+    /// The user state after the last item.
+    state: St,
+    /// Input that has been read, from `start` in the stream.
+    buffer: Vec<u8>,
+    start: usize,
+    /// Bytes of `buffer` that have been parsed into items.
+    consumed: usize,
+    eof: bool,
+    done: bool,
+    input: PhantomData<fn(&S)>,
+}
+
+impl<R, S: ?Sized, F, St> Stream<R, S, F, St> {
+    /// The user state after the items parsed so far.
+    pub fn state(&self) -> &St {
+        &self.state
+    }
+
+    /// Take the user state, e.g. once the stream has ended.
+    pub fn into_state(self) -> St {
+        self.state
+    }
+}
+
+impl<R: Read, S: ?Sized, F, St> Stream<R, S, F, St> {
+    /// Read until at least `needed` more bytes are buffered, or the stream
+    /// ends. Consumed input is dropped from the buffer first.
+    fn fill(&mut self, needed: usize) -> io::Result<()> {
+        self.buffer.drain(..self.consumed);
+        self.start += self.consumed;
+        self.consumed = 0;
+
+        let target = self.buffer.len() + needed;
+        while self.buffer.len() < target && !self.eof {
+            let len = self.buffer.len();
+            // reading as much as is buffered keeps parsing the same item
+            // again linear in its length
+            let n = CHUNK.max(len).max(target - len);
+            self.buffer.resize(len + n, 0);
+            match self.reader.read(&mut self.buffer[len..]) {
+                Ok(read) => {
+                    self.buffer.truncate(len + read);
+                    self.eof = read == 0;
+                }
+                Err(err) => {
+                    self.buffer.truncate(len);
+                    if err.kind() != io::ErrorKind::Interrupted {
+                        return Err(err);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R, S, O, E, St, F> Iterator for Stream<R, S, F, St>
+where
+    R: Read,
+    S: StreamSlice + ?Sized,
+    St: Clone,
+    F: for<'b> Fn(&'b S) -> Parser<'b, &'b S, O, E, St>,
+{
+    type Item = Result<O, StreamError<E>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            if self.consumed == self.buffer.len() {
+                if self.eof {
+                    break;
+                }
+                if let Err(err) = self.fill(1) {
+                    self.done = true;
+                    return Some(Err(err.into()));
+                }
+                continue;
+            }
+
+            let result = match S::from_buffer(&self.buffer[self.consumed..], self.eof) {
+                Ok(input) => {
+                    let mut session = Session::with_state(self.state.clone());
+                    session.set_partial(!self.eof);
+                    match (self.grammar)(input).parse_with(input, 0, &mut session) {
+                        // more input is needed, whatever else failed before,
+                        // and the state is that from before the attempt
+                        Err(
+                            err @ Error {
+                                recover: Recover::Incomplete { .. },
+                                ..
+                            },
+                        ) => Err(err),
+                        result => {
+                            self.state = session.take_state();
+                            session.finish_furthest(result)
+                        }
+                    }
+                }
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err.into()));
+                }
+            };
+
+            match result {
+                Ok((o, at)) => {
+                    self.done = at == 0;
+                    self.consumed += at;
+                    return Some(Ok(o));
+                }
+                Err(Error {
+                    recover: Recover::Incomplete { needed },
+                    ..
+                }) if !self.eof => {
+                    if let Err(err) = self.fill(needed) {
+                        self.done = true;
+                        return Some(Err(err.into()));
+                    }
+                }
+                Err(err) => {
+                    self.done = true;
+                    let offset = self.start + self.consumed;
+                    let err = err.map_offsets(|at| offset + at);
+                    return Some(Err(StreamError::Parse(err)));
+                }
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,28 @@
+//! The pointer and cell types behind parsers, which are thread-safe with the
+//! `sync` feature.
+
+#[cfg(feature = "sync")]
+pub(crate) use std::sync::{Arc as Rc, OnceLock as OnceCell, Weak};
+#[cfg(not(feature = "sync"))]
+pub(crate) use std::{
+    cell::OnceCell,
+    rc::{Rc, Weak},
+};
+
+/// `Send + Sync` with the `sync` feature, and nothing otherwise. Closures and
+/// values that parsers hold on to must implement this, so that with the
+/// feature, parsers are `Send + Sync` too.
+#[cfg(feature = "sync")]
+pub trait MaybeSync: Send + Sync {}
+
+#[cfg(feature = "sync")]
+impl<T: Send + Sync + ?Sized> MaybeSync for T {}
+
+/// `Send + Sync` with the `sync` feature, and nothing otherwise. Closures and
+/// values that parsers hold on to must implement this, so that with the
+/// feature, parsers are `Send + Sync` too.
+#[cfg(not(feature = "sync"))]
+pub trait MaybeSync {}
+
+#[cfg(not(feature = "sync"))]
+impl<T: ?Sized> MaybeSync for T {}
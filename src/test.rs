@@ -1,4 +1,25 @@
-use crate::primitive::{self, unit};
+use std::{
+    collections::BTreeSet,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use crate::{
+    binary::{self, Endian},
+    cst,
+    ext::{self, ParserExt},
+    generate, indent, lexer,
+    location::LineIndex,
+    pratt::{self, Associativity::*},
+    primitive::{self, unit},
+    recovery,
+    report::Report,
+    stream::{self, StreamError},
+    text::{self, Escapes, TextError},
+    trace, Error, Parser, Recover, Session,
+};
 
 #[test]
 fn identity() {
@@ -25,6 +46,8 @@ fn just() {
         primitive::just("hello").parse_at("hello, world!", 0),
         Ok(("hello", 5))
     );
+    // the end of `expected` falls inside the `é`
+    assert_eq!(primitive::just("a").parse("é"), Err(((), 0)));
 }
 
 #[test]
@@ -70,6 +93,22 @@ fn filter_map() {
     let digit = unit().filter_map(|c: char| c.to_digit(10));
     assert_eq!(digit.parse("123"), Ok(1));
     assert_eq!(digit.parse("abc"), Err(((), 0)));
+
+    // like `filter`, fail where the parser started, recording the inner
+    // error, which is reported as it got further
+    let pair = || unit::<str>().then(primitive::just("b"));
+    let filtered = pair().filter(|(c, _)| c.is_ascii_digit());
+    let filter_mapped = pair().filter_map(|(c, _)| c.to_digit(10));
+    let err = filtered
+        .parse_with("1c", 0, &mut Session::new())
+        .unwrap_err();
+    assert_eq!(err.at, 0);
+    let err = filter_mapped
+        .parse_with("1c", 0, &mut Session::new())
+        .unwrap_err();
+    assert_eq!(err.at, 0);
+    assert_eq!(filtered.parse("1c"), Err(((), 1)));
+    assert_eq!(filter_mapped.parse("1c"), Err(((), 1)));
 }
 
 #[test]
@@ -108,3 +147,1353 @@ fn span_input() {
     let p = unit::<str>().many().input().with_span();
     assert_eq!(p.parse("aaaaa"), Ok(("aaaaa", 0..5)));
 }
+
+#[test]
+fn line_index() {
+    let index = LineIndex::new("ab\r\nçd\n");
+
+    assert_eq!(index.line_count(), 3);
+    assert_eq!(index.line_text(1), "ab");
+    assert_eq!(index.line_text(2), "çd");
+    assert_eq!(index.line_text(3), "");
+
+    let location = index.location(6);
+    assert_eq!(
+        (location.line, location.column, location.char_column),
+        (2, 3, 2)
+    );
+    assert_eq!(location.to_string(), "2:2");
+}
+
+#[test]
+fn with_location() {
+    let source = "a\nbcd";
+    let index = LineIndex::new(source);
+    let p = unit::<str>().many().with_location(&index);
+
+    let (_, span) = p.parse(source).unwrap();
+    assert_eq!((span.start.line, span.start.column), (1, 1));
+    assert_eq!((span.end.line, span.end.column), (2, 4));
+}
+
+#[test]
+fn or_merge() {
+    let p = primitive::just("ab")
+        .map_err(|_| vec!["ab"])
+        .or_merge(primitive::just("c").map_err(|_| vec!["c"]));
+    assert_eq!(p.parse("d"), Err((vec!["ab", "c"], 0)));
+
+    // the error that got further is kept over the other
+    let p = primitive::just("a")
+        .right(primitive::just("b"))
+        .map_err(|_| vec!["b"])
+        .or_merge(primitive::just("c").map_err(|_| vec!["c"]));
+    assert_eq!(p.parse("ad"), Err((vec!["b"], 1)));
+
+    // a fatal error of the second parser stays fatal, even if the first got
+    // further, so an enclosing `or` doesn't go on to its next branch
+    let p = primitive::just("a")
+        .then(primitive::just("b"))
+        .or_merge(primitive::just("x").then(primitive::just("y")).expect())
+        .or(primitive::just("a").then(primitive::just("c")));
+    assert_eq!(p.parse("ac"), Err(((), 0)));
+}
+
+#[test]
+fn any_merge() {
+    let p = primitive::any_merge([
+        primitive::just("a").map_err(|_| BTreeSet::from(["a"])),
+        primitive::just("b").map_err(|_| BTreeSet::from(["b"])),
+        primitive::just("a").map_err(|_| BTreeSet::from(["a"])),
+    ]);
+
+    assert_eq!(p.parse("b"), Ok("b"));
+    assert_eq!(p.parse("c"), Err((BTreeSet::from(["a", "b"]), 0)));
+}
+
+#[test]
+fn furthest_error() {
+    let just = |s: &'static str| primitive::just(s).map_err(move |_| s);
+
+    // `optional` backtracks over the missing "c" at offset 2, which is
+    // reported instead of `end` failing at offset 1
+    let p = just("a")
+        .then(just("b").then(just("c")).optional())
+        .left(primitive::end().map_err(|_| "end"));
+    assert_eq!(p.parse("abd"), Err(("c", 2)));
+    assert_eq!(p.parse_furthest("abd"), Err((vec!["c"], 2)));
+    assert_eq!(p.parse("ax"), Err(("end", 1)));
+    assert_eq!(p.parse_furthest("ax"), Err((vec!["b", "end"], 1)));
+
+    let p = just("a").or(just("b"));
+    assert_eq!(p.parse("c"), Err(("b", 0)));
+    assert_eq!(p.parse_furthest("c"), Err((vec!["a", "b"], 0)));
+}
+
+#[test]
+fn report() {
+    let source = "[1,\n\t2 3]";
+    let p = primitive::just("[1,\n\t2 ")
+        .then(primitive::just("]"))
+        .map_err(|_| "expected `]`");
+    let err = p.parse_at(source, 0).unwrap_err();
+
+    let report = Report::from_error(source, &err)
+        .with_label(0..1, "unclosed delimiter")
+        .with_label(7..9, "")
+        .with_note("arrays are separated by commas");
+    assert_eq!(
+        report.to_string(),
+        "\
+error: expected `]`
+ --> 2:4
+  |
+1 | [1,
+  | - unclosed delimiter
+2 |     2 3]
+  |       ^
+  |       --
+  |
+  = note: arrays are separated by commas
+"
+    );
+
+    let report = Report::new(source, 0..1, "oops").with_color(true);
+    assert!(report.to_string().starts_with("\x1b[1;31merror\x1b[0m"));
+
+    // labels inside multibyte chars cover the whole char
+    let source = "«é» ok";
+    let report = Report::new(source, 3..4, "oops").with_label(1..6, "here");
+    assert_eq!(
+        report.to_string(),
+        "\
+error: oops
+ --> 1:2
+  |
+1 | «é» ok
+  |  ^
+  | --- here
+"
+    );
+}
+
+#[test]
+fn recover_with() {
+    let value = primitive::just("1").or(primitive::just("2"));
+
+    // a list of values, where junk before a value is skipped
+    let item = value
+        .clone()
+        .expect()
+        .recover_with(recovery::skip_then_retry_until(primitive::just(",")));
+    let p = item.separate(primitive::just(","));
+    let (output, errors) = p.parse_recovering("1,xy2,1");
+    assert_eq!(output, Some(vec!["1", "2", "1"]));
+    assert_eq!(errors.iter().map(Error::at).collect::<Vec<_>>(), vec![2]);
+
+    // a parenthesized value, skipped entirely if it's broken
+    let group = primitive::just("(")
+        .right(value.expect())
+        .left(primitive::just(")").expect())
+        .recover_with(recovery::nested_delimiters("(", ")", [("[", "]")], || "?"));
+    let p = group.many();
+    let (output, errors) = p.parse_recovering("(1)(2 [()] (x))(2)");
+    assert_eq!(output, Some(vec!["1", "?", "2"]));
+    assert_eq!(errors.iter().map(Error::at).collect::<Vec<_>>(), vec![5]);
+
+    // unbalanced delimiters can't be recovered from
+    let (output, errors) = p.parse_recovering("(1)(2]");
+    assert_eq!(output, None);
+    assert_eq!(errors.iter().map(Error::at).collect::<Vec<_>>(), vec![5]);
+
+    // delimiters are only compared at char boundaries
+    let (output, errors) = p.parse_recovering("(é)");
+    assert_eq!(output, Some(vec!["?"]));
+    assert_eq!(errors.iter().map(Error::at).collect::<Vec<_>>(), vec![1]);
+}
+
+#[test]
+fn recovered_errors_are_backtracked() {
+    let a = primitive::just("a")
+        .expect()
+        .recover_with(recovery::skip_until(primitive::just(";"), || "?"));
+
+    // the first alternative recovers, then fails, so its error is forgotten
+    let p = a
+        .then(primitive::just("!"))
+        .or(primitive::just("x;").map(|x| (x, "")));
+    assert_eq!(p.parse_recovering("x;"), (Some(("x;", "")), vec![]));
+}
+
+#[test]
+fn memoize() {
+    let runs = Arc::new(AtomicUsize::new(0));
+    let counted = {
+        let runs = Arc::clone(&runs);
+        primitive::just("a").map(move |a| {
+            runs.fetch_add(1, Ordering::Relaxed);
+            a
+        })
+    }
+    .memoize();
+
+    // both alternatives start with `counted`, which only runs once
+    let p = counted
+        .clone()
+        .then(primitive::just("b"))
+        .or(counted.then(primitive::just("c")));
+    assert_eq!(p.parse("ac"), Ok(("a", "c")));
+    assert_eq!(runs.load(Ordering::Relaxed), 1);
+
+    // the table doesn't carry over into another parse
+    assert_eq!(p.parse("ac"), Ok(("a", "c")));
+    assert_eq!(runs.load(Ordering::Relaxed), 2);
+    assert_eq!(p.parse("ab"), Ok(("a", "b")));
+    assert_eq!(runs.load(Ordering::Relaxed), 3);
+
+    // nor does it outlive the parse
+    let rc = Arc::new(());
+    let p = {
+        let rc = Arc::clone(&rc);
+        primitive::just("a").map(move |_| Arc::clone(&rc))
+    }
+    .memoize();
+    assert!(p.parse("a").is_ok());
+    assert_eq!(Arc::strong_count(&rc), 2);
+}
+
+#[test]
+fn memoize_replays_errors() {
+    let just = |s: &'static str| primitive::just(s).map_err(move |_| s);
+    let a = just("a").then(just("b").optional()).memoize();
+
+    let p = a.clone().then(just("c")).or(a.then(just("d")));
+    assert_eq!(p.parse_furthest("ax"), Err((vec!["b", "c", "b", "d"], 1)));
+}
+
+#[test]
+fn left_recursion() {
+    let atom = || unit::<str>().filter_map(|c| c.to_digit(10));
+
+    // expr := sum | atom; sum := expr '+' atom
+    // with the left recursion going through another recursive parser, and
+    // a memoized parser whose result depends on the seed
+    let expr = primitive::left_recursive(|expr| {
+        let sum = primitive::recursive(|_| {
+            expr.left(primitive::just("+").ignore_err())
+                .memoize()
+                .then(atom())
+                .map(|(a, b)| a + b)
+        });
+        sum.or(atom())
+    });
+
+    assert_eq!(expr.parse("1"), Ok(1));
+    assert_eq!(expr.parse("1+2+3+4"), Ok(10));
+    // the digit missing after the last `+` is the furthest error
+    assert_eq!(expr.left(primitive::end()).parse("1+2+"), Err(((), 4)));
+}
+
+#[test]
+fn recursion() {
+    // list := '(' list* ')'
+    let list = primitive::recursive(|list| {
+        list.many()
+            .surround(primitive::just("("), primitive::just(")"))
+            .map(|lists| lists.into_iter().sum::<usize>() + 1)
+    });
+
+    assert_eq!(list.parse("(()(()))"), Ok(4));
+    assert_eq!(list.parse("(()"), Err(((), 3)));
+
+    // without left recursion, errors needn't be `Clone` or `Default`
+    #[derive(Debug, PartialEq)]
+    struct Unbalanced;
+    let paren = |p| primitive::just(p).map_err(|_| Unbalanced);
+    let parens = primitive::recursive(|parens| {
+        parens
+            .surround(paren("("), paren(")"))
+            .optional()
+            .map(|_| ())
+    });
+    assert_eq!(parens.left(paren(")")).parse("(()))"), Ok(()));
+}
+
+#[test]
+fn recursive_is_dropped() {
+    let rc = Arc::new(());
+    let list = {
+        let rc = Arc::clone(&rc);
+        primitive::recursive(move |list| {
+            list.many()
+                .surround(primitive::just("["), primitive::just("]"))
+                .map(move |_| Arc::clone(&rc))
+        })
+    };
+    assert!(list.parse("[[]]").is_ok());
+
+    // the grammar doesn't keep itself alive
+    drop(list);
+    assert_eq!(Arc::strong_count(&rc), 1);
+}
+
+#[test]
+fn pratt() {
+    let op = |c| unit::<str>().filter(move |&d| c == d);
+    let binary = |a, op, b| format!("({a}{op}{b})");
+
+    let expr = primitive::recursive(|expr| {
+        let atom = unit::<str>()
+            .filter(char::is_ascii_alphanumeric)
+            .map(String::from)
+            .or(expr.clone().surround(op('('), op(')')));
+        let args = expr.clone().separate(op(',')).surround(op('('), op(')'));
+
+        pratt::pratt(
+            atom,
+            [
+                pratt::ternary(op('?'), op(':'), 1, |c, _, a, _, b| {
+                    format!("({c}?{a}:{b})")
+                }),
+                pratt::infix(op('='), 2, Right, binary),
+                pratt::infix(op('+'), 3, Left, binary),
+                pratt::infix(op('-'), 3, Left, binary),
+                pratt::infix(op('*'), 4, Left, binary),
+                pratt::prefix(op('-'), 5, |op, a| format!("({op}{a})")),
+                pratt::postfix(op('!'), 6, |a, op| format!("({a}{op})")),
+                pratt::postfix(args, 7, |f, args| format!("{f}[{}]", args.join(","))),
+                pratt::postfix(expr.surround(op('['), op(']')), 7, |a, i| {
+                    format!("{a}.{i}")
+                }),
+            ],
+        )
+    });
+
+    assert_eq!(expr.parse("a+b*c-d").unwrap(), "((a+(b*c))-d)");
+    assert_eq!(expr.parse("a=b=c+d").unwrap(), "(a=(b=(c+d)))");
+    assert_eq!(expr.parse("-a!*-b").unwrap(), "((-(a!))*(-b))");
+    assert_eq!(expr.parse("(a+b)*c").unwrap(), "((a+b)*c)");
+    assert_eq!(expr.parse("f(a,b+c)[0]!").unwrap(), "(f[a,(b+c)].0!)");
+    assert_eq!(expr.parse("a?b=c:d?e:f").unwrap(), "(a?(b=c):(d?e:f))");
+
+    // an operator without its right operand
+    assert_eq!(expr.left(primitive::end()).parse("a+b*"), Err(((), 4)));
+}
+
+#[test]
+fn binary_numbers() {
+    let data: &[u8] = &[0x12, 0x34, 0x56, 0x78, 0xff];
+
+    assert_eq!(binary::u16(Endian::Big).parse(data), Ok(0x1234));
+    assert_eq!(binary::u16(Endian::Little).parse(data), Ok(0x3412));
+    assert_eq!(binary::u32(Endian::Big).parse(data), Ok(0x12345678));
+    assert_eq!(binary::i8().parse(&data[4..]), Ok(-1));
+    let half = 1.5f32.to_le_bytes();
+    assert_eq!(binary::f32(Endian::Little).parse(&half), Ok(1.5));
+    assert_eq!(binary::u64(Endian::Big).parse(data), Err(((), 0)));
+}
+
+#[test]
+fn binary_frames() {
+    // frames with a 2 byte length, holding little-endian u16s
+    let frame =
+        binary::length_prefixed(binary::u16(Endian::Big), binary::u16(Endian::Little).many());
+    let frames = frame.clone().many();
+
+    assert_eq!(
+        frames.parse(&[0, 4, 1, 0, 2, 0, 0, 0, 0, 1, 3]),
+        Ok(vec![vec![1, 2], vec![], vec![]])
+    );
+    // the second frame claims more bytes than there are
+    assert_eq!(
+        frames
+            .left(binary::rest().ignore().map_err(|_| None))
+            .parse(&[0, 0, 0, 3, 0]),
+        Ok(vec![vec![]])
+    );
+    assert_eq!(frame.parse(&[0, 3, 0]), Err((None, 0)));
+
+    // the rest of a frame isn't the rest of the input, though both start at
+    // the same offset
+    let rest = binary::rest().map(<[u8]>::len).memoize();
+    let framed = binary::length_prefixed(binary::u8(), rest.clone())
+        .left(primitive::end().map_err(|_| None));
+    let unframed = binary::u8().right(rest).map_err(Some);
+    assert_eq!(framed.or(unframed).parse(&[1, 7, 7]), Ok(2));
+
+    // a 4 bit version and 12 bit length, then a byte
+    let header = binary::bits(binary::take_bits(4).then(binary::take_bits(12))).then(binary::u8());
+    assert_eq!(header.parse(&[0x41, 0x02, 0xff]), Ok(((4, 0x102), 0xff)));
+    assert_eq!(header.parse(&[0x41]), Err(((), 0)));
+
+    // spans of errors in bit fields are in bytes, like the rest
+    let field = binary::u8().right(binary::bits(
+        binary::take_bits(4).right(binary::take_bits(12).context("length")),
+    ));
+    let err = field.parse_at(&[0xff, 0x41], 0).unwrap_err();
+    assert_eq!((err.at, err.context()[0].span.clone()), (1, 1..1));
+
+    // more bytes than any input can hold
+    assert_eq!(
+        binary::u8().right(binary::take(usize::MAX)).parse(&[0]),
+        Err(((), 1))
+    );
+}
+
+#[test]
+fn text_numbers() {
+    assert_eq!(text::integer::<i32>().parse("-42"), Ok(-42));
+    assert_eq!(text::integer::<u8>().parse("0xff"), Ok(255));
+    assert_eq!(text::integer::<u8>().parse("0b101"), Ok(5));
+    assert_eq!(text::integer::<i64>().parse("0o17"), Ok(15));
+    assert_eq!(text::integer::<i8>().parse("-128"), Ok(-128));
+    assert_eq!(
+        text::integer::<i8>().parse("128"),
+        Err((TextError::Overflow, 0))
+    );
+    assert_eq!(
+        text::integer::<u8>().parse("-1"),
+        Err((TextError::Unexpected, 0))
+    );
+
+    assert_eq!(text::float::<f64>().parse("-1.5e3"), Ok(-1500.0));
+    assert_eq!(text::float::<f64>().parse(".25"), Ok(0.25));
+    assert_eq!(text::float::<f32>().parse("1e99"), Ok(f32::INFINITY));
+    assert_eq!(
+        text::float::<f64>().parse("e5"),
+        Err((TextError::Unexpected, 0))
+    );
+
+    // types of other crates may reject numbers of the right syntax
+    #[derive(Debug, PartialEq)]
+    struct Decimal(u32);
+    impl text::Integer for Decimal {
+        const SIGNED: bool = false;
+
+        fn from_str_radix(src: &str, radix: u32) -> Result<Self, std::num::ParseIntError> {
+            let src = if radix == 10 { src } else { "" };
+            src.parse().map(Decimal)
+        }
+    }
+    assert_eq!(text::integer().parse("12"), Ok(Decimal(12)));
+    assert_eq!(
+        text::integer::<Decimal>().parse("0x12"),
+        Err((TextError::Unexpected, 0))
+    );
+
+    #[derive(Debug, PartialEq)]
+    struct Finite(f64);
+    impl std::str::FromStr for Finite {
+        type Err = ();
+
+        fn from_str(s: &str) -> Result<Self, ()> {
+            let n: f64 = s.parse().map_err(|_| ())?;
+            n.is_finite().then_some(Finite(n)).ok_or(())
+        }
+    }
+    impl text::Float for Finite {}
+    assert_eq!(text::float().parse("1e9"), Ok(Finite(1e9)));
+    assert_eq!(
+        text::float::<Finite>().parse("1e999"),
+        Err((TextError::Unexpected, 0))
+    );
+}
+
+#[test]
+fn text_strings() {
+    let string = text::string('"', Escapes::json());
+    assert_eq!(
+        string.parse(r#""a\"\n\u00e9\ud83d\ude00""#).unwrap(),
+        "a\"\né😀"
+    );
+    assert_eq!(string.parse(r#""a\x""#), Err((TextError::InvalidEscape, 2)));
+    assert_eq!(
+        string.parse(r#""\ud83d""#),
+        Err((TextError::InvalidEscape, 1))
+    );
+    assert_eq!(string.parse(r#""abc"#), Err((TextError::Unterminated, 0)));
+
+    let string = text::string('\'', Escapes::new().with('\'', '\''));
+    assert_eq!(string.parse(r"'it\'s'").unwrap(), "it's");
+
+    // escapes cut off by the end of partial input wait for more, invalid
+    // ones don't
+    let string = text::string('"', Escapes::json());
+    let incomplete = |input| {
+        let mut session = Session::new();
+        session.set_partial(true);
+        let err = string.parse_with(input, 0, &mut session).unwrap_err();
+        matches!(err.recover(), Recover::Incomplete { .. })
+    };
+    assert!(incomplete(r#""\ud83d\ude"#));
+    assert!(!incomplete(r#""\x"#));
+    assert!(!incomplete(r#""\u12g"#));
+    assert!(!incomplete(r#""\ud83d\u00"#));
+
+    assert_eq!(text::identifier().parse("_ñame1 = 2"), Ok("_ñame1"));
+    assert_eq!(text::identifier().parse("e\u{301}x"), Ok("e\u{301}x"));
+    assert_eq!(
+        text::identifier().parse("1x"),
+        Err((TextError::Unexpected, 0))
+    );
+    assert_eq!(
+        text::identifier().parse("½"),
+        Err((TextError::Unexpected, 0))
+    );
+    assert_eq!(text::identifier().parse("x²"), Ok("x"));
+}
+
+#[test]
+fn xid() {
+    let start = |c: char| {
+        let s = c.to_string();
+        let ok = text::identifier().parse(&s).is_ok();
+        ok
+    };
+    let cont = |c: char| {
+        let s = format!("a{c}");
+        let ok = text::identifier().parse(&s) == Ok(&s);
+        ok
+    };
+
+    // the ends of ranges of the tables, around letters of Latin-1
+    assert!(!start('@') && start('A') && start('Z') && !start('['));
+    assert!(start('\u{d6}') && !start('\u{d7}') && start('\u{d8}'));
+    assert!(!cont('\u{d7}'));
+    // `XID_Continue` only
+    assert!(!start('0') && cont('0') && cont('9'));
+    assert!(!start('\u{b7}') && cont('\u{b7}'));
+    // letters that aren't `XID_Start` as they change under NFKC
+    assert!(!start('\u{e33}') && cont('\u{e33}'));
+    assert!(!start('\u{309b}') && !cont('\u{309b}'));
+    // CJK Extension J, added in Unicode 17.0, ends the last range of letters
+    assert!(start('\u{323b0}') && start('\u{33479}') && !start('\u{3347a}'));
+    // variation selectors
+    assert!(!start('\u{e0100}') && cont('\u{e0100}') && cont('\u{e01ef}'));
+    assert!(!cont('\u{e01f0}'));
+}
+
+#[test]
+fn ext() {
+    // a statically dispatched parser, with a boxed one inside it
+    let p = ext::just("a")
+        .then(primitive::just("b").or(primitive::just("c")))
+        .map(|(a, b)| format!("{a}{b}"))
+        .many()
+        .left(ext::from_fn(|input: &str, at, _| match at == input.len() {
+            true => Ok(((), at)),
+            false => Err(Error::new((), at)),
+        }));
+
+    assert_eq!(
+        p.parse("abac"),
+        Ok(vec!["ab".to_string(), "ac".to_string()])
+    );
+    // errors backtracked over are reported the same way
+    assert_eq!(p.parse("abad"), Err(((), 3)));
+    assert_eq!(p.boxed().parse("abad"), Err(((), 3)));
+
+    let digit = ext::unit::<str>().filter(char::is_ascii_digit).optional();
+    assert_eq!(digit.parse("1"), Ok(Some('1')));
+    assert_eq!(digit.parse("x"), Ok(None));
+
+    // and with user state
+    let digits = ext::Unit::<str, usize>::new()
+        .filter(char::is_ascii_digit)
+        .then(ext::from_fn(|_: &str, at, session| {
+            *session.state() += 1;
+            Ok(((), at))
+        }))
+        .many()
+        .left(ext::Just::new(";"));
+    assert_eq!(digits.parse_with_state("12;", 0).1, 2);
+}
+
+#[cfg(feature = "sync")]
+#[test]
+fn sync() {
+    use std::sync::OnceLock;
+
+    use crate::Parser;
+
+    fn grammar() -> &'static Parser<'static, &'static str, u32, ()> {
+        static GRAMMAR: OnceLock<Parser<&str, u32, ()>> = OnceLock::new();
+        GRAMMAR.get_or_init(|| {
+            primitive::recursive(|list| {
+                list.many()
+                    .surround(primitive::just("("), primitive::just(")"))
+                    .map(|lists| lists.into_iter().sum::<u32>() + 1)
+                    .memoize()
+            })
+        })
+    }
+
+    let threads: Vec<_> = (0..4)
+        .map(|_| std::thread::spawn(|| grammar().parse("(()(()))")))
+        .collect();
+    for thread in threads {
+        assert_eq!(thread.join().unwrap(), Ok(4));
+    }
+}
+
+#[test]
+fn state() {
+    type Names = Vec<char>;
+    let just = |s| primitive::just(s).stateful::<Names>();
+    let name = || {
+        unit::<str>()
+            .filter(char::is_ascii_lowercase)
+            .stateful::<Names>()
+    };
+    // `let x;` declares `x`, and `x;` uses it
+    let declare = just("let ")
+        .right(name())
+        .map_with_state(|name, names| names.push(name));
+    let refer = name()
+        .then(primitive::get_state())
+        .filter(|(name, names)| names.contains(name))
+        .ignore();
+    let p = declare
+        .clone()
+        .or(refer)
+        .left(just(";"))
+        .many()
+        .left(primitive::end().stateful());
+
+    assert_eq!(
+        p.parse_with_state("let a;a;let b;b;a;", Vec::new()),
+        (Ok(vec![(); 5]), vec!['a', 'b'])
+    );
+    assert_eq!(p.parse_with_state("let a;b;", Vec::new()).0, Err(((), 6)));
+
+    // a declaration that is backtracked over is only forgotten in a scope,
+    // whether the scope fails or what follows it does
+    let names = || primitive::get_state();
+    let p = declare.clone().left(just("!")).or(just("let a").ignore());
+    assert_eq!(
+        p.right(names()).parse_with_state("let a", Vec::new()).0,
+        Ok(vec!['a'])
+    );
+    let p = declare
+        .clone()
+        .left(just("!"))
+        .with_state_scope()
+        .or(just("let a").ignore());
+    assert_eq!(
+        p.right(names()).parse_with_state("let a", Vec::new()).0,
+        Ok(vec![])
+    );
+    let p = declare
+        .with_state_scope()
+        .left(just("!"))
+        .or(just("let a").ignore());
+    assert_eq!(
+        p.right(names()).parse_with_state("let a", Vec::new()).0,
+        Ok(vec![])
+    );
+}
+
+#[test]
+fn state_in_modules() {
+    let just = |s| primitive::just(s).stateful::<u32>();
+    let count = |n: &mut u32| *n += 1;
+
+    // the operands of a pratt parser
+    let atom = unit::<str>()
+        .filter_map(|c| c.to_digit(10))
+        .stateful()
+        .map_with_state(|d, n| {
+            count(n);
+            d
+        });
+    let expr = pratt::pratt(atom, [pratt::infix(just("+"), 1, Left, |a, _, b| a + b)]);
+    assert_eq!(expr.parse_with_state("1+2+3", 0), (Ok(6), 3));
+
+    // statements that are recovered from
+    let statement = just("x")
+        .left(just(";").expect())
+        .recover_with(recovery::skip_until(just(";"), || "error"))
+        .map_with_state(|o, n| {
+            count(n);
+            o
+        });
+    let (output, errors, n) = statement.many().parse_recovering_with_state("x;x!;x;", 0);
+    assert_eq!(output, Some(vec!["x", "error", "x"]));
+    assert_eq!((errors.len(), n), (1, 3));
+
+    // the items of an indented block
+    let item = just("a\n")
+        .map_with_state(|_, n| count(n))
+        .map_err(|_| None);
+    let block = just("x:\n")
+        .map_err(|_| None)
+        .right(indent::indented_block(item));
+    assert_eq!(
+        block.parse_with_state("x:\n  a\n  a\n", 0),
+        (Ok(vec![(), ()]), 2)
+    );
+
+    // statically dispatched parsers
+    let read = ext::from_fn(|_: &str, at, session: &mut Session<(), u32>| {
+        count(session.state());
+        Ok(((), at))
+    });
+    assert_eq!(
+        read.clone().then(read).parse_with_state("", 0),
+        (Ok(((), ())), 2)
+    );
+
+    // tokens
+    let tokens = lexer::lexer([lexer::rule(0, 0, primitive::just("t"))])
+        .parse("tt")
+        .unwrap();
+    let token = lexer::token(0).map_with_state(|_, n| count(n));
+    assert_eq!(
+        token.many().parse_with_state(&tokens, 0),
+        (Ok(vec![(), ()]), 2)
+    );
+
+    // items of a stream, counted once each, however often they are attempted
+    fn counted<'a>() -> Parser<'a, &'a str, (), (), u32> {
+        primitive::just("a")
+            .stateful()
+            .map_with_state(|_, n| *n += 1)
+            .left(primitive::just(";").stateful())
+    }
+    let mut items = stream::parse_stream_with_state(Trickle(b"a;a;a;"), 0, |_| counted());
+    assert_eq!(items.by_ref().map(Result::unwrap).count(), 3);
+    assert_eq!(items.into_state(), 3);
+
+    // generated input
+    generate::assert_parses(|_| counted(), 0..10, 1);
+}
+
+/// A reader that hands out its input a few bytes at a time.
+struct Trickle<'a>(&'a [u8]);
+
+impl std::io::Read for Trickle<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = buf.len().min(self.0.len()).min(3);
+        buf[..n].copy_from_slice(&self.0[..n]);
+        self.0 = &self.0[n..];
+        Ok(n)
+    }
+}
+
+/// A reader that hands out its input in chunks of the given length.
+struct Chunks<'a>(&'a [u8], usize);
+
+impl std::io::Read for Chunks<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = buf.len().min(self.0.len()).min(self.1);
+        buf[..n].copy_from_slice(&self.0[..n]);
+        self.0 = &self.0[n..];
+        Ok(n)
+    }
+}
+
+/// A reader that hands out all of its input at once, and must not be read
+/// again after that.
+struct Exhausted<'a>(&'a [u8], bool);
+
+impl std::io::Read for Exhausted<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        assert!(!self.1, "read past the input that was needed");
+        self.1 = true;
+        buf[..self.0.len()].copy_from_slice(self.0);
+        Ok(self.0.len())
+    }
+}
+
+#[test]
+fn stream() {
+    fn line<'a>() -> Parser<'a, &'a str, String, TextError> {
+        text::string('"', Escapes::json())
+            .left(primitive::just("\n").map_err(|_| TextError::Unexpected))
+    }
+    let input = "\"héllo\"\n\"wo\\u0072ld\"\n\"!\"\n".as_bytes();
+    let lines: Vec<_> = stream::parse_stream(Trickle(input), |_| line())
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(lines, vec!["héllo", "world", "!"]);
+
+    // running out of input is only an error once the stream has ended
+    let mut lines = stream::parse_stream(Trickle(b"\"a\"\n\"b"), |_| line());
+    assert_eq!(lines.next().unwrap().unwrap(), "a");
+    match lines.next() {
+        Some(Err(StreamError::Parse(err))) => {
+            assert_eq!(
+                (err.inner, err.at, err.recover()),
+                (TextError::Unterminated, 4, Recover::Recoverable)
+            );
+        }
+        _ => panic!("expected an unterminated string"),
+    }
+    assert!(lines.next().is_none());
+
+    // and other errors don't wait for more input
+    let mut numbers = stream::parse_stream(Trickle(b"\x00\x01\x00\x02\xff"), |_| {
+        binary::u16(Endian::Big).filter(|&n| n < 2)
+    });
+    assert_eq!(numbers.next().unwrap().unwrap(), 1);
+    assert!(matches!(
+        numbers.next(),
+        Some(Err(StreamError::Parse(Error { at: 2, .. })))
+    ));
+
+    // errors of later items are at offsets into the stream, context and all,
+    // and the one that got furthest is reported
+    fn pair<'a>() -> Parser<'a, &'a str, (), ()> {
+        primitive::just("a")
+            .then(primitive::just("b").expect())
+            .context("pair")
+            .ignore()
+    }
+    let mut pairs = stream::parse_stream(Trickle(b"abax"), |_| pair());
+    pairs.next().unwrap().unwrap();
+    match pairs.next() {
+        Some(Err(StreamError::Parse(err))) => {
+            assert_eq!((err.at, err.context()[0].span.clone()), (3, 2..3));
+        }
+        _ => panic!("expected a missing `b`"),
+    }
+    fn item<'a>() -> Parser<'a, &'a str, (), ()> {
+        let just = primitive::just::<str>;
+        just("x").then(just("y")).ignore().or(just("z").ignore())
+    }
+    let mut items = stream::parse_stream(Trickle(b"zxq"), |_| item());
+    items.next().unwrap().unwrap();
+    assert!(matches!(
+        items.next(),
+        Some(Err(StreamError::Parse(Error { at: 2, .. })))
+    ));
+
+    // the end of a frame, or of an offside block, is the end of the input of
+    // the parser in it, so the item is complete without reading any further
+    let mut frames =
+        stream::parse_stream(Exhausted(b"\x00\x04\x01\x00\x02\x00\x00", false), |_| {
+            binary::length_prefixed(binary::u16(Endian::Big), binary::u16(Endian::Little).many())
+        });
+    assert_eq!(frames.next().unwrap().unwrap(), vec![1, 2]);
+    let mut blocks = stream::parse_stream(Exhausted(b"one\n  two\nthr", false), |_| {
+        indent::offside(unit::<str>().many())
+            .input()
+            .map(String::from)
+    });
+    assert_eq!(blocks.next().unwrap().unwrap(), "one\n  two\n");
+
+    // a block goes on past the input read so far, wherever the reads split it
+    type Tree = (String, Vec<String>);
+    fn tree<'a>() -> Parser<'a, &'a str, Tree, Option<indent::IndentError>> {
+        let line = || {
+            crate::util::recognize_input(|c: &char| c.is_alphanumeric())
+                .left(primitive::just("\n"))
+                .map(String::from)
+                .map_err(|_| None)
+        };
+        line().then(indent::indented_block(line()))
+    }
+    let node = |name: &str, children: &[&str]| -> Tree {
+        (
+            name.to_string(),
+            children.iter().map(|c| c.to_string()).collect(),
+        )
+    };
+    let expected = vec![node("a", &["b", "c"]), node("d", &["e"])];
+    let input = b"a\n  b\n  c\nd\n  e\n";
+    for chunk in 1..=input.len() {
+        let trees: Vec<_> = stream::parse_stream(Chunks(input, chunk), |_| tree())
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(trees, expected);
+    }
+
+    let incomplete = primitive::just::<str>("abc").map_err(|_| ());
+    let mut session = Session::new();
+    session.set_partial(true);
+    let err = incomplete.parse_with("ab", 0, &mut session).unwrap_err();
+    assert_eq!(err.recover(), Recover::Incomplete { needed: 1 });
+}
+
+#[test]
+fn lexer() {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    enum Kind {
+        Slash,
+        Word,
+    }
+
+    let lex = lexer::lexer([
+        lexer::rule(Kind::Slash, 0, primitive::just("/")),
+        lexer::rule(Kind::Word, 0, text::identifier()),
+        lexer::skip(primitive::just(" ")),
+        // a comment is longer than the `/` it starts with
+        lexer::skip(primitive::just("//").then(unit::<str>().filter(|&c| c != '\n').many())),
+    ]);
+    let tokens = lex.parse("a / b // c").unwrap();
+    let tokens: Vec<_> = tokens
+        .iter()
+        .map(|token| (token.kind, token.text, token.span()))
+        .collect();
+    assert_eq!(
+        tokens,
+        vec![
+            (Kind::Word, "a", 0..1),
+            (Kind::Slash, "/", 2..3),
+            (Kind::Word, "b", 4..5)
+        ]
+    );
+    assert_eq!(lex.parse("a ? b"), Err(((), 2)));
+
+    let tokens = lex.parse("a /").unwrap();
+    let p = lexer::token(Kind::Word)
+        .then(lexer::token(Kind::Slash))
+        .then(lexer::token(Kind::Word));
+    let (err, at) = p.parse(&tokens).unwrap_err();
+    assert_eq!(
+        (err.expected, err.found, err.span, at),
+        (Kind::Word, None, 3..3, 2)
+    );
+}
+
+#[test]
+fn spans() {
+    let digit = || unit::<str>().filter(char::is_ascii_digit).labelled("digit");
+    let number = digit()
+        .then(digit().expect())
+        .then(digit().expect())
+        .labelled("number");
+    let list = number
+        .clone()
+        .left(primitive::just(","))
+        .many()
+        .labelled("list");
+
+    // the innermost rule that consumed input is blamed
+    let err = list.parse_at("123,12a", 0).unwrap_err();
+    assert_eq!((err.label(), err.span()), (Some("number"), 4..6));
+    // a rule that fails where it starts is described by the rule around it
+    let err = number.parse_at("a", 0).unwrap_err();
+    assert_eq!((err.label(), err.span()), (Some("number"), 0..0));
+
+    let p = primitive::just("ab")
+        .spanned()
+        .then(primitive::just("c").spanned());
+    let (ab, c) = p.parse("abc").unwrap();
+    assert_eq!(
+        (*ab.value(), ab.span(), c.into_parts()),
+        ("ab", 0..2, ("c", 2..3))
+    );
+}
+
+#[test]
+fn labels() {
+    let literal = |s| primitive::just(s).map_err(|_| Vec::new()).labelled(s);
+    let value = primitive::any_merge([literal("true"), literal("null")]).labelled("value");
+    assert_eq!(value.parse_furthest("x"), Err((vec![vec!["value"]], 0)));
+    // failing after consuming input keeps the inner error
+    let value = value.then(literal("!")).labelled("exclamation");
+    assert_eq!(value.parse("true?"), Err((vec!["!"], 4)));
+
+    let list = literal("[")
+        .right(value.clone())
+        .then(literal(",").right(value.expect()).many())
+        .left(literal("]"))
+        .map_err(|expected| expected.join(" or "));
+    let source = "[true!,]";
+    let err = list.parse_at(source, 0).unwrap_err();
+    let report = Report::expected(source, &err);
+    assert_eq!(
+        report.to_string().lines().next(),
+        Some("error: expected exclamation, found ']'")
+    );
+}
+
+#[test]
+fn context() {
+    let just = primitive::just::<str>;
+    let member = just("\"a\": ")
+        .then(just("1").expect())
+        .context("object member");
+    let object = just("{\n  ")
+        .then(member.separate(just(",\n  ")))
+        .then(just("\n}"))
+        .context("object");
+    let p = just("[").then(object).context("array");
+
+    let source = "[{\n  \"a\": 1,\n  \"a\": 2\n}]";
+    let err = p.parse_at(source, 0).unwrap_err();
+    let labels: Vec<_> = err.context().iter().map(|frame| frame.label).collect();
+    assert_eq!(labels, vec!["object member", "object", "array"]);
+    assert_eq!(err.context()[0].span, 15..20);
+
+    let report = Report::from_error_with(source, &err, |_| "expected `1`".to_string());
+    assert_eq!(
+        report.to_string(),
+        "\
+error: expected `1`
+ --> 3:8
+  |
+3 |   \"a\": 2
+  |        ^
+  |
+  = note: while parsing object member at 3:3
+  = note: while parsing object at 1:2
+  = note: while parsing array at 1:1
+"
+    );
+}
+
+#[test]
+fn trace() {
+    let digit = unit::<str>().filter(char::is_ascii_digit).trace("digit");
+    let p = digit
+        .clone()
+        .then(digit.expect())
+        .trace("pair")
+        .many()
+        .trace("pairs");
+
+    let (result, trace) = p.parse_traced("123");
+    assert_eq!(result, Err(((), 3)));
+    assert_eq!(
+        trace.to_string(),
+        "\
+pairs 0: fatal error at 3
+  pair 0..2
+    digit 0..1
+    digit 1..2
+  pair 2: fatal error at 3
+    digit 2..3
+    digit 3: recoverable error at 3
+"
+    );
+    assert_eq!(
+        trace.attempts[0].attempts[1].outcome,
+        trace::Outcome::Err {
+            at: 3,
+            recover: Recover::Fatal
+        }
+    );
+
+    let (_, trace) = primitive::just("\"").trace("quote\"").parse_traced("x");
+    assert_eq!(
+        trace.to_json(),
+        r#"[{"rule":"quote\"","start":0,"error":{"at":0,"recover":"recoverable"},"attempts":[]}]"#
+    );
+}
+
+#[test]
+fn cst() {
+    use crate::util::recognize_input;
+    use cst::{node, token, SyntaxElement, SyntaxNode};
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    enum Kind {
+        List,
+        Atom,
+        Paren,
+        Trivia,
+    }
+
+    // lists of atoms, with whitespace and `;` comments between them
+    let trivia = token(
+        Kind::Trivia,
+        primitive::any([
+            recognize_input(|c: &char| c.is_whitespace()),
+            primitive::just(";")
+                .then(unit::<str>().filter(|&c| c != '\n').many())
+                .input(),
+        ]),
+    )
+    .many();
+    let atom = token(Kind::Atom, recognize_input(|c: &char| c.is_alphanumeric()));
+    let list = primitive::recursive(|list| {
+        let element = atom
+            .clone()
+            .map(cst::GreenElement::Token)
+            .or(list.map(cst::GreenElement::Node));
+        node(
+            Kind::List,
+            token(Kind::Paren, primitive::just("("))
+                .then(trivia.clone())
+                .then(element.then(trivia.clone()).many())
+                .then(token(Kind::Paren, primitive::just(")"))),
+        )
+    });
+
+    let source = "(a ; one\n  (b c)  )";
+    let tree = SyntaxNode::new_root(list.parse(source).unwrap());
+    assert_eq!(tree.text(), source);
+    assert_eq!(tree.span(), 0..source.len());
+
+    let inner = tree.node_at(13).unwrap();
+    assert_eq!(
+        (inner.kind(), inner.span(), inner.text()),
+        (Kind::List, 11..16, "(b c)".to_string())
+    );
+    assert_eq!(inner.parent().unwrap().span(), tree.span());
+    assert_eq!(tree.node_at(1).unwrap().span(), tree.span());
+    assert!(tree.node_at(source.len()).is_none());
+
+    let comment = tree.token_at(5).unwrap();
+    assert_eq!((comment.kind(), comment.text()), (Kind::Trivia, "; one"));
+    let kinds: Vec<_> = inner.children().iter().map(SyntaxElement::kind).collect();
+    assert_eq!(
+        kinds,
+        [
+            Kind::Paren,
+            Kind::Atom,
+            Kind::Trivia,
+            Kind::Atom,
+            Kind::Paren
+        ]
+    );
+}
+
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic(expected = "the input consumed in a node must be covered by its tokens")]
+fn cst_lossy() {
+    // the `b` isn't in any token
+    let p = cst::node(
+        'n',
+        cst::token('a', primitive::just("a")).left(primitive::just("b")),
+    );
+    let _ = p.parse("ab");
+}
+
+#[test]
+fn incremental() {
+    use crate::util::recognize_input;
+    use cst::{node, token};
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    enum Kind {
+        List,
+        Atom,
+        Paren,
+        Space,
+    }
+
+    static LISTS_PARSED: AtomicUsize = AtomicUsize::new(0);
+    let space = token(Kind::Space, primitive::just(" ")).optional();
+    let atom = token(Kind::Atom, recognize_input(|c: &char| c.is_alphanumeric()));
+    let list = primitive::recursive(|list| {
+        let element = atom
+            .clone()
+            .map(cst::GreenElement::Token)
+            .or(list.map(cst::GreenElement::Node));
+        node(
+            Kind::List,
+            token(Kind::Paren, primitive::just("("))
+                .then(element.then(space.clone()).many())
+                .then(token(Kind::Paren, primitive::just(")"))),
+        )
+        .map(|list| {
+            LISTS_PARSED.fetch_add(1, Ordering::Relaxed);
+            list
+        })
+        .memoize()
+    });
+
+    let (tree, version) = list.parse_versioned("(a (b c) (d e))");
+    assert!(tree.is_ok());
+    assert_eq!(LISTS_PARSED.load(Ordering::Relaxed), 3);
+
+    // the lists around the edit are parsed again, the one after it is reused
+    let (edited, version) = list.reparse("(a (b cc) (d e))", version, (6..7, "cc"));
+    assert_eq!(LISTS_PARSED.load(Ordering::Relaxed), 5);
+
+    // only the list that read the edited input is parsed again
+    let (tree, _) = list.reparse("(ab (b cc) (d e))", version, (2..2, "b"));
+    assert_eq!(LISTS_PARSED.load(Ordering::Relaxed), 6);
+
+    assert_eq!(edited, list.parse("(a (b cc) (d e))"));
+    assert_eq!(tree, list.parse("(ab (b cc) (d e))"));
+
+    // spans after the edit aren't reused from before it
+    let word = recognize_input(|c: &char| c.is_alphabetic())
+        .with_span()
+        .memoize();
+    let sentence = word.clone().then(primitive::just(" ").right(word).many());
+    let (_, version) = sentence.parse_versioned("one two three");
+    let (edited, _) = sentence.reparse("one second three", version, (4..7, "second"));
+    assert_eq!(edited, sentence.parse("one second three"));
+}
+
+#[test]
+fn indent() {
+    use crate::util::recognize_input;
+    use indent::{indented_block, same_indent, IndentError};
+
+    #[derive(Clone, Debug, Default, PartialEq)]
+    enum Error {
+        Indent(IndentError),
+        #[default]
+        Unexpected,
+    }
+
+    impl From<IndentError> for Error {
+        fn from(err: IndentError) -> Self {
+            Error::Indent(err)
+        }
+    }
+
+    // statements, some of which end in `:` and are followed by a block
+    let statements = primitive::recursive(|statement| {
+        let name = recognize_input(|c: &char| c.is_alphanumeric()).map_err(|_| Error::Unexpected);
+        let block = just_error(":\n").right(indented_block(statement));
+        name.then(block.or(just_error("\n").map(|_| Vec::new())))
+            .map(|(name, body)| (name, body.len()))
+            .memoize()
+    });
+    let program = same_indent()
+        .right(statements.clone())
+        .many()
+        .left(primitive::end().map_err(|_| Error::Unexpected));
+
+    let source = "if:\n  a\n\n  while:\n    b\n    c\n\n  d\ne\n";
+    assert_eq!(program.parse(source), Ok(vec![("if", 3), ("e", 0)]));
+
+    assert_eq!(
+        program.parse("if:\n  a\n    b\n"),
+        Err((
+            Error::Indent(IndentError::Misaligned {
+                expected: 2,
+                found: 4
+            }),
+            12
+        ))
+    );
+    assert_eq!(
+        program.parse("if:\n    a\n  b\n"),
+        Err((Error::Indent(IndentError::UnmatchedDedent { found: 2 }), 12))
+    );
+
+    // the statement after an edit to the indentation before it isn't reused,
+    // as its block is no longer indented past the one around it
+    let (_, version) = program.parse_versioned("if:\n  a:\n   b\n");
+    let edited = "if:\n    a:\n   b\n";
+    let (reparsed, _) = program.reparse(edited, version, (4..4, "  "));
+    assert!(reparsed.is_err());
+    assert_eq!(reparsed, program.parse(edited));
+
+    // a statement in a block is parsed apart from one at the same offset
+    // outside of it, where the next line is indented past it
+    let in_block = just_error("x:\n")
+        .right(indented_block(statements.clone()))
+        .map(|block| block.len());
+    let alone = just_error("x:\n  ").right(statements).map(|(_, body)| body);
+    assert_eq!(in_block.or(alone).parse("x:\n  a:\n  b\n"), Ok(1));
+
+    // nor is an offside value after an edit to the lines it scanned, which
+    // may end its input elsewhere, even if the value didn't read that far
+    static VALUES_PARSED: AtomicUsize = AtomicUsize::new(0);
+    let value = indent::offside(primitive::just("one"))
+        .map(|value| {
+            VALUES_PARSED.fetch_add(1, Ordering::Relaxed);
+            value
+        })
+        .memoize();
+    let (_, version) = value.parse_versioned("one two");
+    let (reparsed, _) = value.reparse("one t\nwo", version, (5..5, "\n"));
+    assert_eq!(reparsed, value.parse("one t\nwo"));
+    assert_eq!(VALUES_PARSED.load(Ordering::Relaxed), 3);
+
+    fn just_error<'a>(s: &'static str) -> Parser<'a, &'a str, &'a str, Error> {
+        primitive::just(s).map_err(|_| Error::Unexpected)
+    }
+}
+
+#[test]
+fn grammar() {
+    use primitive::{any, just, recursive};
+
+    // list := "(" [ item { "," item } [ "," ] ] ")"
+    let list = recursive(|list| {
+        let atom = any([just("a"), just("b")]).map(|_| 1).labelled("atom");
+        let item = atom.or(list.map(|items: Vec<i32>| items.len() as i32));
+        item.separate(just(","))
+            .surround(just("("), just(")"))
+            .labelled("list")
+    });
+    assert_eq!(list.parse("(a,(b,),())"), Ok(vec![1, 1, 0]));
+
+    assert_eq!(
+        list.grammar().to_string(),
+        "\
+list = \"(\" , [ ( atom | list ) , { \",\" , ( atom | list ) } , [ \",\" ] ] , \")\" ;
+atom = \"a\" | \"b\" ;
+"
+    );
+
+    let exclamation = just("!").many_with(Some(1), Some(3)).map_err(|_| ());
+    let greeting = just("hi").then(unit::<str>().optional().then(exclamation));
+    assert_eq!(
+        greeting.grammar().to_string(),
+        "grammar = \"hi\" , [ ? unknown ? ] , \"!\" , 2 * [ \"!\" ] ;\n"
+    );
+    assert_eq!(
+        greeting.grammar().to_json(),
+        r#"[{"name":"grammar","body":{"type":"sequence","items":[{"type":"terminal","text":"\"hi\""},{"type":"repeat","item":{"type":"unknown"},"min":0,"max":1},{"type":"repeat","item":{"type":"terminal","text":"\"!\""},"min":1,"max":3}]}}]"#
+    );
+
+    // parsers of unknown structure, and those wrapping them, have no node
+    // until one is asked for
+    let digit = unit::<str>().filter(char::is_ascii_digit).map(|c| c as u8);
+    assert!(digit.grammar.is_none());
+    assert_eq!(digit.grammar().to_string(), "grammar = ? unknown ? ;\n");
+}
+
+#[test]
+fn generate() {
+    use generate::{assert_parses, generate};
+    use primitive::{any, end, just, recursive};
+
+    fn value<'a>() -> Parser<'a, &'a str, (), ()> {
+        recursive(|value| {
+            let digit = any(["0", "1", "2", "3", "4", "5", "6", "7", "8", "9"].map(just));
+            let number = just("-")
+                .optional()
+                .then(digit.many_with(Some(1), Some(4)).map_err(|_| ()));
+            let array = value.separate(just(",")).surround(just("["), just("]"));
+            any([number.ignore(), array.ignore(), just("null").ignore()])
+        })
+        .left(end())
+    }
+    assert_parses(|_| value(), 0..200, 4);
+
+    let input = generate(&value().grammar(), 1, 4).unwrap();
+    assert_eq!(generate(&value().grammar(), 1, 4), Some(input));
+
+    // bytes, and parsers whose structure is unknown
+    let header = just(b"\x7fELF".as_slice()).then(any([just(b"\x01".as_slice()), just(b"\x02")]));
+    let bytes = generate(&header.grammar(), 0, 0).unwrap();
+    assert!(bytes == b"\x7fELF\x01" || bytes == b"\x7fELF\x02");
+    assert_eq!(generate(&unit::<str>().grammar(), 0, 4), None);
+
+    // an alternative that a choice before it shadows is generated, but only
+    // the other parses
+    fn shadowed<'a>() -> Parser<'a, &'a str, (), ()> {
+        any([just("a"), just("ab")]).left(end()).ignore()
+    }
+    assert_eq!(generate(&shadowed().grammar(), 1, 0).unwrap(), "ab");
+    assert!(shadowed().parse("ab").is_err());
+    assert!(shadowed().parse("a").is_ok());
+}
+
+#[test]
+#[should_panic(expected = "the input generated from seed 0, \"a\", failed with () at 1")]
+fn generate_unparsable() {
+    use generate::{assert_parses, generate};
+    use primitive::{end, just};
+
+    // `many` takes every `a`, leaving none for the last
+    fn greedy<'a>() -> Parser<'a, &'a str, (), ()> {
+        just("a").many().then(just("a")).left(end()).ignore()
+    }
+    assert_eq!(generate(&greedy().grammar(), 0, 1).unwrap(), "a");
+    assert!(greedy().parse("a").is_err());
+    assert_parses(|_| greedy(), 0..1, 1);
+}
@@ -0,0 +1,357 @@
+//! Parsers for common lexical elements of text: numbers, identifiers and
+//! quoted strings. Their errors are [`TextError`]s.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use hair::{primitive::just, text::{self, Escapes}};
+//!
+//! let assignment = text::identifier()
+//!     .left(just(" = ").map_err(|_| text::TextError::Unexpected))
+//!     .then(text::string('"', Escapes::json()));
+//!
+//! assert_eq!(
+//!     assignment.parse(r#"name = "héllo""#),
+//!     Ok(("name", "héllo".to_string()))
+//! );
+//! ```
+
+use std::{borrow::Cow, cmp::Ordering, num::IntErrorKind, str::FromStr};
+
+use crate::{
+    primitive::{self, just, unit},
+    util::recognize_input,
+    Error, Parser,
+};
+
+mod xid;
+
+/// The error of the parsers in this module.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextError {
+    /// The input doesn't start with what the parser expected.
+    Unexpected,
+    /// An integer doesn't fit in its type.
+    Overflow,
+    /// An escape sequence in a string isn't one of the allowed [`Escapes`],
+    /// or is an unpaired surrogate.
+    InvalidEscape,
+    /// A string is missing its closing quote. The error is at the opening
+    /// quote.
+    Unterminated,
+}
+
+/// Integer types that can be parsed by [`integer`]. Digits that
+/// `from_str_radix` rejects other than for overflow, e.g. those of a radix
+/// the type doesn't support, fail with [`TextError::Unexpected`].
+pub trait Integer: Sized {
+    const SIGNED: bool;
+
+    fn from_str_radix(src: &str, radix: u32) -> Result<Self, std::num::ParseIntError>;
+}
+
+macro_rules! integers {
+    ($($ty:ident: $signed:literal),*) => {$(
+        impl Integer for $ty {
+            const SIGNED: bool = $signed;
+
+            fn from_str_radix(src: &str, radix: u32) -> Result<Self, std::num::ParseIntError> {
+                $ty::from_str_radix(src, radix)
+            }
+        }
+    )*};
+}
+
+integers!(
+    u8: false, u16: false, u32: false, u64: false, u128: false, usize: false,
+    i8: true, i16: true, i32: true, i64: true, i128: true, isize: true
+);
+
+/// Floating point types that can be parsed by [`float`]. Numbers that
+/// `from_str` rejects, e.g. those out of the range of the type, fail with
+/// [`TextError::Unexpected`].
+pub trait Float: FromStr {}
+
+impl Float for f32 {}
+impl Float for f64 {}
+
+fn sign<'a>() -> Parser<'a, &'a str, char, ()> {
+    unit().filter(|&c| c == '+' || c == '-')
+}
+
+fn digits<'a>(radix: u32) -> Parser<'a, &'a str, &'a str, ()> {
+    recognize_input(move |c: &char| c.is_digit(radix))
+}
+
+/// Parse an integer: decimal digits, or hexadecimal, octal or binary digits
+/// prefixed with `0x`, `0o` or `0b`. Signed types may have a leading `+` or
+/// `-`. Fails with [`TextError::Overflow`] if the number doesn't fit in `T`.
+pub fn integer<'a, T: Integer + 'a>() -> Parser<'a, &'a str, T, TextError> {
+    let sign = if T::SIGNED {
+        sign().optional()
+    } else {
+        primitive::identity().map(|_| None)
+    };
+    let radix = primitive::any([
+        just("0x").map(|_| 16),
+        just("0o").map(|_| 8),
+        just("0b").map(|_| 2),
+    ])
+    .optional()
+    .map(|radix| radix.unwrap_or(10));
+    let number = sign
+        .then(radix.flat_map(|radix| digits(radix).map(move |digits| (radix, digits))))
+        .map_err(|_| TextError::Unexpected);
+
+    Parser::new(move |input, at, session| {
+        let (result, records) = session.capture(|session| number.parse_with(input, at, session));
+        let ((sign, (radix, digits)), rest) = match result {
+            Ok(ok) => ok,
+            Err(err) => {
+                session.replay(records);
+                return Err(err);
+            }
+        };
+        let digits = match sign {
+            Some('-') => Cow::Owned(format!("-{digits}")),
+            _ => Cow::Borrowed(digits),
+        };
+
+        match T::from_str_radix(&digits, radix) {
+            Ok(n) => {
+                session.replay(records);
+                Ok((n, rest))
+            }
+            Err(err) => match err.kind() {
+                // the digits that could have followed don't matter when there
+                // are too many already, so only the overflow is reported
+                IntErrorKind::PosOverflow | IntErrorKind::NegOverflow => {
+                    session.replay(records.recovered_only());
+                    Err(Error::new(TextError::Overflow, at))
+                }
+                _ => {
+                    session.replay(records.recovered_only());
+                    Err(Error::new(TextError::Unexpected, at))
+                }
+            },
+        }
+    })
+}
+
+/// Parse a floating point number, such as `1`, `-0.5`, `.5` or `6.02e23`. A
+/// number too large for `T` is infinite.
+pub fn float<'a, T: Float + 'a>() -> Parser<'a, &'a str, T, TextError> {
+    let digits = || digits(10);
+    let mantissa = digits()
+        .then(just(".").then(digits().optional()).optional())
+        .ignore()
+        .or(just(".").then(digits()).ignore());
+    let exponent = unit::<str>()
+        .filter(|&c| c == 'e' || c == 'E')
+        .then(sign().optional())
+        .then(digits());
+
+    let number = sign()
+        .optional()
+        .then(mantissa)
+        .then(exponent.optional())
+        .input()
+        .map_err(|_| TextError::Unexpected);
+    let grammar = number.grammar.clone();
+
+    Parser::new(move |input, at, session| {
+        let (result, records) = session.capture(|session| number.parse_with(input, at, session));
+        let (number, rest) = match result {
+            Ok(ok) => ok,
+            Err(err) => {
+                session.replay(records);
+                return Err(err);
+            }
+        };
+
+        match number.parse() {
+            Ok(n) => {
+                session.replay(records);
+                Ok((n, rest))
+            }
+            // as with integers, only the rejected number is reported
+            Err(_) => {
+                session.replay(records.recovered_only());
+                Err(Error::new(TextError::Unexpected, at))
+            }
+        }
+    })
+    .with_grammar(grammar)
+}
+
+/// Parse an identifier: a character with the Unicode property `XID_Start`
+/// or `_`, followed by any number of characters with the property
+/// `XID_Continue`, like the identifiers of Rust.
+pub fn identifier<'a>() -> Parser<'a, &'a str, &'a str, TextError> {
+    unit::<str>()
+        .filter(|&c| c == '_' || is_in(xid::START, c))
+        .then(recognize_input(|&c: &char| is_in(xid::CONTINUE, c)).optional())
+        .input()
+        .map_err(|_| TextError::Unexpected)
+}
+
+/// Whether `c` is in one of the sorted `ranges`.
+fn is_in(ranges: &[(char, char)], c: char) -> bool {
+    ranges
+        .binary_search_by(|&(start, end)| {
+            if end < c {
+                Ordering::Less
+            } else if start > c {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+/// The escape sequences allowed in a [`string`], each a backslash followed by
+/// a character.
+#[derive(Clone, Debug, Default)]
+pub struct Escapes {
+    simple: Vec<(char, char)>,
+    unicode: bool,
+}
+
+impl Escapes {
+    /// No escape sequences at all.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The escapes of JSON strings: `\"`, `\\`, `\/`, `\b`, `\f`, `\n`, `\r`,
+    /// `\t` and `\uXXXX`.
+    pub fn json() -> Self {
+        Self::new()
+            .with('"', '"')
+            .with('\\', '\\')
+            .with('/', '/')
+            .with('b', '\u{8}')
+            .with('f', '\u{c}')
+            .with('n', '\n')
+            .with('r', '\r')
+            .with('t', '\t')
+            .with_unicode()
+    }
+
+    /// Allow `\` followed by `escape`, standing for `c`.
+    pub fn with(mut self, escape: char, c: char) -> Self {
+        self.simple.push((escape, c));
+        self
+    }
+
+    /// Allow `\uXXXX`, with four hexadecimal digits standing for a UTF-16
+    /// code unit. A surrogate must be followed by the other of its pair,
+    /// e.g. `\ud83d\ude00` for `😀`.
+    pub fn with_unicode(self) -> Self {
+        Self {
+            unicode: true,
+            ..self
+        }
+    }
+
+    /// Unescape the escape sequence at `at`, returning the character and the
+    /// offset after the sequence.
+    fn unescape(&self, input: &str, at: usize) -> Option<(char, usize)> {
+        let escape = input[at + 1..].chars().next()?;
+        if let Some(&(_, c)) = self.simple.iter().find(|(e, _)| *e == escape) {
+            return Some((c, at + 1 + escape.len_utf8()));
+        }
+        if escape != 'u' || !self.unicode {
+            return None;
+        }
+
+        let code_unit = |at: usize| {
+            let digits = input.get(at..at + 4)?;
+            digits
+                .chars()
+                .all(|c| c.is_ascii_hexdigit())
+                .then(|| u16::from_str_radix(digits, 16).unwrap())
+        };
+
+        let high = code_unit(at + 2)?;
+        let (units, end) = if (0xd800..0xdc00).contains(&high) {
+            let low = input
+                .get(at + 6..at + 8)
+                .filter(|&u| u == "\\u")
+                .and_then(|_| code_unit(at + 8))?;
+            (vec![high, low], at + 12)
+        } else {
+            (vec![high], at + 6)
+        };
+
+        let c = char::decode_utf16(units).next()?.ok()?;
+        Some((c, end))
+    }
+
+    /// Whether `escape`, which runs to the end of the input, is the start of
+    /// an escape sequence that more input could complete.
+    fn is_prefix(&self, escape: &str) -> bool {
+        let mut chars = escape.chars().skip(1);
+        match chars.next() {
+            None => self.unicode || !self.simple.is_empty(),
+            Some('u') if self.unicode => {
+                // four hexadecimal digits, and if they're the high half of a
+                // surrogate pair, `\u` and the low half
+                let digits: Vec<char> = chars.collect();
+                let high: String = digits.iter().take(4).collect();
+                let valid = digits.iter().enumerate().all(|(i, &c)| match i {
+                    0..=3 | 8..=9 => c.is_ascii_hexdigit(),
+                    4 => c == '\\',
+                    5 => c == 'u',
+                    6 => c.eq_ignore_ascii_case(&'d'),
+                    7 => matches!(c.to_ascii_lowercase(), 'c'..='f'),
+                    _ => false,
+                });
+                valid
+                    && (digits.len() < 4
+                        || (0xd800..0xdc00).contains(&u16::from_str_radix(&high, 16).unwrap()))
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Parse a string between two `quote`s, in which a `\` starts one of the
+/// `escapes`. Outputs the string with its escapes replaced.
+pub fn string<'a>(quote: char, escapes: Escapes) -> Parser<'a, &'a str, String, TextError> {
+    let open = unit::<str>()
+        .filter(move |&c| c == quote)
+        .map_err(|_| TextError::Unexpected);
+
+    Parser::new(move |input: &'a str, start, session| {
+        let (_, mut at) = open.parse_with(input, start, session)?;
+        let mut string = String::new();
+        loop {
+            let Some(c) = input[at..].chars().next() else {
+                session.read_to(input.len() + 1);
+                return Err(session.out_of_input(TextError::Unterminated, start, 1));
+            };
+
+            if c == quote {
+                session.read_to(at + c.len_utf8());
+                return Ok((string, at + c.len_utf8()));
+            } else if c == '\\' {
+                session.read_to(at + 12);
+                let Some((c, rest)) = escapes.unescape(input, at) else {
+                    // the escape may just be cut off
+                    return Err(if escapes.is_prefix(&input[at..]) {
+                        session.out_of_input(TextError::InvalidEscape, at, 1)
+                    } else {
+                        Error::new(TextError::InvalidEscape, at)
+                    });
+                };
+                string.push(c);
+                at = rest;
+            } else {
+                string.push(c);
+                at += c.len_utf8();
+            }
+        }
+    })
+}
@@ -0,0 +1,170 @@
+//! Recording what a parser attempted, for debugging grammars. Parsers marked
+//! with [`Parser::trace`] record an [`Attempt`] each time they run, when
+//! parsing with [`Parser::parse_traced`].
+//!
+//! ## Example
+//!
+//! ```rust
+//! use hair::primitive::{any, just};
+//!
+//! let p = any([just("ab").trace("ab"), just("a").trace("a")]).trace("either");
+//! let (result, trace) = p.parse_traced("a");
+//! assert_eq!(result, Ok("a"));
+//! assert_eq!(
+//!     trace.to_string(),
+//!     "\
+//! either 0..1
+//!   ab 0: recoverable error at 0
+//!   a 0..1
+//! "
+//! );
+//! ```
+
+use std::fmt::{self, Write};
+
+use crate::Recover;
+
+/// The attempts recorded by [`Parser::parse_traced`](crate::Parser::parse_traced),
+/// in the order they started.
+///
+/// Its [`Display`](fmt::Display) implementation prints one attempt per line,
+/// indented under the attempt it was part of.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Trace {
+    pub attempts: Vec<Attempt>,
+}
+
+/// A run of a parser marked with [`Parser::trace`](crate::Parser::trace).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Attempt {
+    pub rule: &'static str,
+    pub start: usize,
+    pub outcome: Outcome,
+    /// The attempts of traced parsers that this one ran.
+    pub attempts: Vec<Attempt>,
+}
+
+/// How an [`Attempt`] ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The parser succeeded, consuming the input up to `end`.
+    Ok { end: usize },
+    /// The parser failed with an error at `at`.
+    Err { at: usize, recover: Recover },
+}
+
+impl Trace {
+    /// Export the trace as JSON: an array of attempts, each an object with the
+    /// fields `rule`, `start`, `end` or `error` (an object with `at` and
+    /// `recover`), and `attempts`.
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        write_json(&mut json, &self.attempts);
+        json
+    }
+}
+
+fn write_json(json: &mut String, attempts: &[Attempt]) {
+    json.push('[');
+    for (i, attempt) in attempts.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        json.push_str("{\"rule\":");
+        write_json_string(json, attempt.rule);
+        write!(json, ",\"start\":{},", attempt.start).unwrap();
+        match attempt.outcome {
+            Outcome::Ok { end } => write!(json, "\"end\":{end}"),
+            Outcome::Err { at, recover } => write!(
+                json,
+                "\"error\":{{\"at\":{at},\"recover\":\"{}\"}}",
+                recover_name(recover)
+            ),
+        }
+        .unwrap();
+        json.push_str(",\"attempts\":");
+        write_json(json, &attempt.attempts);
+        json.push('}');
+    }
+    json.push(']');
+}
+
+/// Write `s` as a JSON string.
+pub(crate) fn write_json_string(json: &mut String, s: &str) {
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if c.is_control() => write!(json, "\\u{:04x}", c as u32).unwrap(),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+fn recover_name(recover: Recover) -> &'static str {
+    match recover {
+        Recover::Recoverable => "recoverable",
+        Recover::Fatal => "fatal",
+        Recover::Incomplete { .. } => "incomplete",
+    }
+}
+
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn write(f: &mut fmt::Formatter<'_>, attempts: &[Attempt], depth: usize) -> fmt::Result {
+            for attempt in attempts {
+                write!(f, "{:indent$}{} ", "", attempt.rule, indent = depth * 2)?;
+                match attempt.outcome {
+                    Outcome::Ok { end } => writeln!(f, "{}..{end}", attempt.start)?,
+                    Outcome::Err { at, recover } => writeln!(
+                        f,
+                        "{}: {} error at {at}",
+                        attempt.start,
+                        recover_name(recover)
+                    )?,
+                }
+                write(f, &attempt.attempts, depth + 1)?;
+            }
+            Ok(())
+        }
+
+        write(f, &self.attempts, 0)
+    }
+}
+
+/// The attempts being recorded in a session.
+#[derive(Default)]
+pub(crate) struct Tracer {
+    /// Attempts that haven't ended, outermost first, with the attempts they
+    /// ran so far.
+    running: Vec<(&'static str, usize, Vec<Attempt>)>,
+    done: Vec<Attempt>,
+}
+
+impl Tracer {
+    pub(crate) fn enter(&mut self, rule: &'static str, start: usize) {
+        self.running.push((rule, start, Vec::new()));
+    }
+
+    pub(crate) fn exit(&mut self, outcome: Outcome) {
+        let (rule, start, attempts) = self.running.pop().expect("exited an attempt never entered");
+        let attempt = Attempt {
+            rule,
+            start,
+            outcome,
+            attempts,
+        };
+        match self.running.last_mut() {
+            Some((_, _, parent)) => parent.push(attempt),
+            None => self.done.push(attempt),
+        }
+    }
+
+    pub(crate) fn finish(self) -> Trace {
+        Trace {
+            attempts: self.done,
+        }
+    }
+}